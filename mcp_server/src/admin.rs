@@ -0,0 +1,190 @@
+//! Operator methods outside of MCP, enabled with `--admin`.
+//!
+//! These are served to any client of the server, so only enable them where every
+//! client is trusted.
+
+use std::sync::Arc;
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::lifecycle::Handler;
+use crate::progress::Progress;
+use crate::resources;
+use crate::session::{Meta, SessionStore};
+use crate::tools::{self, SavedSearch, ToolRegistry};
+use crate::watch::DbWatcher;
+
+pub const REINDEX: &str = "admin/reindex";
+pub const ADD_TOOL: &str = "admin/tools/add";
+pub const REMOVE_TOOL: &str = "admin/tools/remove";
+
+/// What the admin methods act on.
+#[derive(Clone, Debug)]
+pub struct AdminContext {
+    pub db: Arc<DbWatcher>,
+    pub tools: ToolRegistry,
+    pub sessions: SessionStore,
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoveToolParams {
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReindexResult {
+    lines: usize,
+    changed_lines: usize,
+}
+
+/// Registers the admin methods on `handler`.
+pub fn register(handler: &mut Handler, admin: AdminContext) {
+    let admin_tools = admin.clone();
+    handler.add_method(ADD_TOOL, move |params: Params| {
+        let admin = admin_tools.clone();
+        async move {
+            log::debug!("RPC '{}' method called with params: {:?}", ADD_TOOL, params);
+            let saved = params.parse::<SavedSearch>()?;
+            let name = saved.name.clone();
+            let changed = admin.tools.add(saved)?;
+            if changed {
+                log::info!("Saved search tool '{}' added", name);
+                tools::notify_list_changed(&admin.sessions);
+            }
+            Ok(json!({ "changed": changed }))
+        }
+    });
+
+    let admin_tools = admin.clone();
+    handler.add_method(REMOVE_TOOL, move |params: Params| {
+        let admin = admin_tools.clone();
+        async move {
+            log::debug!("RPC '{}' method called with params: {:?}", REMOVE_TOOL, params);
+            let params = params.parse::<RemoveToolParams>()?;
+            let changed = admin.tools.remove(&params.name);
+            if changed {
+                log::info!("Saved search tool '{}' removed", params.name);
+                tools::notify_list_changed(&admin.sessions);
+            }
+            Ok(json!({ "changed": changed }))
+        }
+    });
+
+    handler.add_method_with_meta(REINDEX, move |params: Params, session: Meta| {
+        let admin = admin.clone();
+        async move {
+            log::debug!("RPC '{}' method called with params: {:?}", REINDEX, params);
+            let mut progress = Progress::for_request(&session, &params);
+            let db = Arc::clone(&admin.db);
+            // Reading and indexing the file is blocking work
+            let change = tokio::task::spawn_blocking(move || {
+                db.reload(|done, total| progress.report(done, total, format!("Indexed {} of {} lines", done, total)))
+            })
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+            .map_err(|e| {
+                log::error!("Reindex requested by session {} failed: {}", session.id, e);
+                Error {
+                    code: ErrorCode::InternalError,
+                    message: format!("Failed to reindex: {}", e),
+                    data: None,
+                }
+            })?;
+            if !change.is_empty() {
+                resources::notify_changes(&admin.sessions, &change);
+            }
+            let result = ReindexResult {
+                lines: admin.db.index().load().lines.len(),
+                changed_lines: change.changed_lines.len(),
+            };
+            log::info!("Session {} reindexed the database: {:?}", session.id, result);
+            serde_json::to_value(result).map_err(|e| {
+                log::error!("Failed to serialize ReindexResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::progress::PROGRESS_NOTIFICATION;
+    use crate::session::Session;
+    use crate::watch::SharedIndex;
+    use crate::WordIndex;
+    use serde_json::Value;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_reindex_reports_progress() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "first line").unwrap();
+        let index = SharedIndex::new(WordIndex::new(file.path().to_str().unwrap()).unwrap());
+        let db = Arc::new(DbWatcher::new(file.path(), index.clone()));
+        let mut handler = lifecycle::new_handler();
+        register(
+            &mut handler,
+            AdminContext {
+                db,
+                tools: ToolRegistry::default(),
+                sessions: SessionStore::default(),
+            },
+        );
+
+        writeln!(file, "second line").unwrap();
+        let session = Arc::new(Session::stateless());
+        let mut events = session.attach_standalone();
+        let request = r#"{"jsonrpc": "2.0", "method": "admin/reindex", "params": {"_meta": {"progressToken": 5}}, "id": 1}"#;
+        let response: Value = serde_json::from_str(&handler.handle_request(request, session).await.unwrap()).unwrap();
+        assert_eq!(response["result"], serde_json::json!({"lines": 2, "changedLines": 1}));
+        assert_eq!(index.load().search("second"), vec![1]);
+
+        let event: Value = serde_json::from_str(&events.try_recv().unwrap().data).unwrap();
+        assert_eq!(event["method"], PROGRESS_NOTIFICATION);
+        assert_eq!(event["params"]["progressToken"], 5);
+        assert_eq!(event["params"]["progress"], 2);
+        assert_eq!(event["params"]["total"], 2);
+    }
+
+    #[test]
+    fn test_tool_changes_are_broadcast() {
+        let index = SharedIndex::new(WordIndex::new("test_db.txt").unwrap());
+        let sessions = SessionStore::default();
+        let watcher = Arc::new(Session::new("watcher".into()));
+        watcher.initialize("2025-06-18", Value::Null).unwrap();
+        watcher.mark_initialized();
+        sessions.register(Arc::clone(&watcher));
+        let mut events = watcher.attach_standalone();
+        let tools = ToolRegistry::default();
+        let mut handler = lifecycle::new_handler();
+        register(
+            &mut handler,
+            AdminContext {
+                db: Arc::new(DbWatcher::new("test_db.txt", index)),
+                tools: tools.clone(),
+                sessions,
+            },
+        );
+        let call = |request: &str| -> Value {
+            serde_json::from_str(&handler.handle_request_sync(request, Arc::new(Session::stateless())).unwrap()).unwrap()
+        };
+
+        let add = r#"{"jsonrpc": "2.0", "method": "admin/tools/add", "params": {"name": "greetings", "description": "Greetings.", "query": "hello"}, "id": 1}"#;
+        assert_eq!(call(add)["result"]["changed"], true);
+        assert!(tools.saved_search("greetings").is_some());
+        let event: Value = serde_json::from_str(&events.try_recv().unwrap().data).unwrap();
+        assert_eq!(event["method"], tools::LIST_CHANGED_NOTIFICATION);
+
+        assert_eq!(call(add)["result"]["changed"], false);
+        assert!(events.try_recv().is_err(), "unchanged list is not announced");
+
+        let remove = r#"{"jsonrpc": "2.0", "method": "admin/tools/remove", "params": {"name": "greetings"}, "id": 2}"#;
+        assert_eq!(call(remove)["result"]["changed"], true);
+        assert!(events.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,233 @@
+//! Request cancellation: `notifications/cancelled` stops the request it names.
+//!
+//! [`RequestTracker`] runs every method call as its own tokio task, registered with
+//! the calling session under its JSON-RPC id along with a [`CancellationToken`].
+//! Cancelling aborts the task at its next await point and trips the token, which
+//! long synchronous work such as [`WordIndex::search_cancellable`] polls so it can
+//! give up early. A cancelled request gets no response.
+//!
+//! [`WordIndex::search_cancellable`]: crate::WordIndex::search_cancellable
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use jsonrpc_http_server::jsonrpc_core::futures_util::future::Either;
+use jsonrpc_http_server::jsonrpc_core::middleware::{Middleware, NoopCallFuture, NoopFuture};
+use jsonrpc_http_server::jsonrpc_core::{Call, Error, ErrorCode, Id, Output, Params};
+use serde::Deserialize;
+
+use crate::lifecycle::Handler;
+use crate::session::Meta;
+
+pub const CANCELLED_NOTIFICATION: &str = "notifications/cancelled";
+
+/// Error code for a request that stopped because it was cancelled.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// A flag set when the request it belongs to is cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CurrentRequest {
+    id: Id,
+    token: CancellationToken,
+    /// The id of the session that sent the request.
+    session: String,
+}
+
+tokio::task_local! {
+    static CURRENT: CurrentRequest;
+}
+
+/// The token of the request being handled on this task. Outside of a tracked
+/// request this is a token that is never cancelled.
+///
+/// Methods must call this from the future they return, not from the closure
+/// creating it: the token is only in scope while the future is polled.
+pub fn current() -> CancellationToken {
+    CURRENT.try_with(|request| request.token.clone()).unwrap_or_default()
+}
+
+/// The JSON-RPC id of the request being handled on this task, under the same
+/// conditions as [`current`].
+pub fn current_id() -> Option<Id> {
+    CURRENT.try_with(|request| request.id.clone()).ok()
+}
+
+/// The id of the session whose request is being handled on this task, under
+/// the same conditions as [`current`].
+pub fn current_session() -> Option<String> {
+    CURRENT.try_with(|request| request.session.clone()).ok()
+}
+
+pub fn cancelled_error() -> Error {
+    Error {
+        code: ErrorCode::ServerError(REQUEST_CANCELLED),
+        message: "Request cancelled".into(),
+        data: None,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CancelledParams {
+    request_id: Id,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Middleware tracking method calls per session so they can be cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct RequestTracker;
+
+/// Unregisters a request from its session once its response is done or dropped.
+struct Tracked {
+    session: Meta,
+    id: Id,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.session.finish_request(&self.id);
+    }
+}
+
+impl Middleware<Meta> for RequestTracker {
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: Call, session: Meta, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, Meta) -> X + Send + Sync,
+        X: std::future::Future<Output = Option<Output>> + Send + 'static,
+    {
+        let id = match &call {
+            Call::MethodCall(method_call) => method_call.id.clone(),
+            _ => return Either::Right(next(call, session)),
+        };
+        let token = CancellationToken::default();
+        let current = CurrentRequest {
+            id: id.clone(),
+            token: token.clone(),
+            session: session.id.clone(),
+        };
+        let response = CURRENT.scope(current, next(call, Arc::clone(&session)));
+
+        // Without a runtime (as in synchronous tests) the call runs inline and can
+        // only be stopped through its token.
+        let (response, task): (NoopCallFuture, _) = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let task = runtime.spawn(response);
+                let abort = task.abort_handle();
+                (Box::pin(async move { task.await.ok().flatten() }), Some(abort))
+            }
+            Err(_) => (Box::pin(response), None),
+        };
+        session.track_request(id.clone(), token.clone(), task);
+        let tracked = Tracked { session, id };
+
+        Either::Left(Box::pin(async move {
+            let output = response.await;
+            if token.is_cancelled() {
+                log::debug!("Session {} dropping response to cancelled request {:?}", tracked.session.id, tracked.id);
+                return None;
+            }
+            output
+        }))
+    }
+}
+
+/// Registers the `notifications/cancelled` handler on `handler`.
+pub fn register(handler: &mut Handler) {
+    handler.add_notification_with_meta(CANCELLED_NOTIFICATION, |params: Params, session: Meta| {
+        log::debug!("RPC '{}' notification received with params: {:?}", CANCELLED_NOTIFICATION, params);
+        match params.parse::<CancelledParams>() {
+            Ok(params) => {
+                if session.cancel_request(&params.request_id) {
+                    log::info!(
+                        "Session {} cancelled request {:?} (reason: {})",
+                        session.id,
+                        params.request_id,
+                        params.reason.as_deref().unwrap_or("none given")
+                    );
+                } else {
+                    log::debug!("Session {} has no request {:?} in flight to cancel", session.id, params.request_id);
+                }
+            }
+            Err(e) => log::warn!("Ignoring malformed {}: {}", CANCELLED_NOTIFICATION, e.message),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::session::Session;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancelled_request_is_aborted_without_response() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler);
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel::<CancellationToken>();
+        let started_tx = std::sync::Mutex::new(Some(started_tx));
+        handler.add_method("slow", move |_params: Params| {
+            let started_tx = started_tx.lock().unwrap().take();
+            async move {
+                if let Some(tx) = started_tx {
+                    let _ = tx.send(current());
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(serde_json::json!("finished"))
+            }
+        });
+        let session = Arc::new(Session::stateless());
+
+        let pending = tokio::spawn({
+            let handler = handler.clone();
+            let session = Arc::clone(&session);
+            async move {
+                handler
+                    .handle_request(r#"{"jsonrpc": "2.0", "method": "slow", "id": 7}"#, session)
+                    .await
+            }
+        });
+        let token = started_rx.await.unwrap();
+        assert!(!token.is_cancelled());
+
+        let cancel = r#"{"jsonrpc": "2.0", "method": "notifications/cancelled", "params": {"requestId": 7, "reason": "user"}}"#;
+        assert_eq!(handler.handle_request(cancel, Arc::clone(&session)).await, None);
+        assert!(token.is_cancelled());
+        let response = tokio::time::timeout(Duration::from_secs(5), pending)
+            .await
+            .expect("cancelled request should finish promptly")
+            .unwrap();
+        assert_eq!(response, None);
+        assert!(!session.cancel_request(&Id::Num(7)), "finished requests are no longer tracked");
+    }
+
+    #[tokio::test]
+    async fn test_completed_requests_are_untracked() {
+        let mut handler = lifecycle::new_handler();
+        handler.add_method("quick", |_params: Params| async { Ok(serde_json::json!(current_session())) });
+        let session = Arc::new(Session::stateless());
+        let response = handler
+            .handle_request(r#"{"jsonrpc": "2.0", "method": "quick", "id": "a"}"#, Arc::clone(&session))
+            .await;
+        assert_eq!(response.as_deref(), Some(r#"{"jsonrpc":"2.0","result":"stateless","id":"a"}"#));
+        assert_eq!(current_session(), None, "only known while handling a request");
+        assert!(!session.cancel_request(&Id::Str("a".into())));
+    }
+}
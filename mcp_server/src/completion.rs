@@ -0,0 +1,325 @@
+//! Argument completion via `completion/complete`.
+//!
+//! Words typed into a search query (the search tool's `query`, or a prompt's
+//! search argument) complete to vocabulary terms, most frequent first. Line
+//! numbers (the fetch tool's `line`, the `db://` resource templates) complete to
+//! existing lines.
+//!
+//! MCP only defines `ref/prompt` and `ref/resource` references; tool arguments are
+//! completed for the non-standard `{"type": "ref/tool", "name": ...}`.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Params};
+use serde::{Deserialize, Serialize};
+
+use crate::lifecycle::Handler;
+use crate::prompts::PromptTemplate;
+use crate::resources::{LINE_TEMPLATE, RANGE_TEMPLATE};
+use crate::session::Meta;
+use crate::tools::{FETCH_TOOL, SEARCH_TOOL};
+use crate::watch::SharedIndex;
+use crate::{Posting, WordIndex};
+
+/// Most completion values returned at once, as the protocol allows.
+pub const MAX_VALUES: usize = 100;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum Reference {
+    #[serde(rename = "ref/prompt")]
+    Prompt { name: String },
+    #[serde(rename = "ref/resource")]
+    Resource { uri: String },
+    #[serde(rename = "ref/tool")]
+    Tool { name: String },
+}
+
+#[derive(Deserialize, Debug)]
+struct Argument {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Context {
+    #[serde(default)]
+    arguments: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompleteParams {
+    #[serde(rename = "ref")]
+    reference: Reference,
+    argument: Argument,
+    #[serde(default)]
+    context: Context,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Completion {
+    pub values: Vec<String>,
+    pub total: usize,
+    pub has_more: bool,
+}
+
+impl Completion {
+    fn from_ranked(ranked: Vec<String>) -> Self {
+        let total = ranked.len();
+        let values: Vec<String> = ranked.into_iter().take(MAX_VALUES).collect();
+        Completion {
+            has_more: total > values.len(),
+            values,
+            total,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CompleteResult {
+    completion: Completion,
+}
+
+/// Completes the last word of `query` to vocabulary terms, ranked by the number
+/// of lines they appear on and then alphabetically. Each value is the whole
+/// query with its last word completed, keeping any operator, quote or
+/// parenthesis in front of it.
+pub fn complete_query(wi: &WordIndex, query: &str) -> Completion {
+    let (head, partial) = match query.rfind(char::is_whitespace) {
+        Some(at) => query.split_at(at + 1),
+        None => ("", query),
+    };
+    let word_start = partial.find(char::is_alphanumeric).unwrap_or(partial.len());
+    let (lead, partial) = partial.split_at(word_start);
+    let head = format!("{}{}", head, lead);
+    let prefix: String = partial.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect();
+    if prefix.is_empty() {
+        return Completion::default();
+    }
+    let mut terms: Vec<(&String, usize)> = wi
+        .words_with_prefix(&prefix)
+        .iter()
+        .map(|term| (term, document_frequency(&wi.index[term])))
+        .collect();
+    terms.sort_by(|(a, a_freq), (b, b_freq)| b_freq.cmp(a_freq).then_with(|| a.cmp(b)));
+    Completion::from_ranked(terms.into_iter().map(|(term, _)| format!("{}{}", head, term)).collect())
+}
+
+/// Number of distinct lines in a word's occurrences, which may repeat a line.
+fn document_frequency(postings: &[Posting]) -> usize {
+    let mut previous = None;
+    postings
+        .iter()
+        .filter(|posting| previous.replace(posting.line) != Some(posting.line))
+        .count()
+}
+
+/// Completes `typed` to line numbers of `wi` that start with it, in order,
+/// skipping lines before `from`.
+pub fn complete_line(wi: &WordIndex, typed: &str, from: usize) -> Completion {
+    let typed = typed.trim();
+    if !typed.chars().all(|c| c.is_ascii_digit()) {
+        return Completion::default();
+    }
+    let ranges = line_ranges(typed, from, wi.lines.len());
+    let total = ranges.iter().map(ExactSizeIterator::len).sum();
+    let values: Vec<String> = ranges.into_iter().flatten().take(MAX_VALUES).map(|line| line.to_string()).collect();
+    Completion {
+        has_more: total > values.len(),
+        values,
+        total,
+    }
+}
+
+/// The numbers from `from` up to `end` whose digits start with `typed`, as
+/// ascending ranges: the number `typed` spells, then those one digit longer,
+/// and so on.
+fn line_ranges(typed: &str, from: usize, end: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    if typed.is_empty() {
+        ranges.push(from..end.max(from));
+        return ranges;
+    }
+    if typed.starts_with('0') {
+        // No other number starts with a zero
+        if typed == "0" && from == 0 && end > 0 {
+            ranges.push(0..1);
+        }
+        return ranges;
+    }
+    let Ok(number) = typed.parse::<usize>() else {
+        return ranges;
+    };
+    let mut scale = 1usize;
+    while let Some(start) = number.checked_mul(scale).filter(|&start| start < end) {
+        let stop = (number + 1).saturating_mul(scale).min(end);
+        if from.max(start) < stop {
+            ranges.push(from.max(start)..stop);
+        }
+        let Some(next) = scale.checked_mul(10) else { break };
+        scale = next;
+    }
+    ranges
+}
+
+fn complete(wi: &WordIndex, templates: &[PromptTemplate], params: &CompleteParams) -> Result<Completion, Error> {
+    let argument = &params.argument;
+    let none = Ok(Completion::default());
+    match &params.reference {
+        Reference::Prompt { name } => {
+            let template = templates
+                .iter()
+                .find(|t| &t.name == name)
+                .ok_or_else(|| invalid_params(format!("Unknown prompt: {}", name)))?;
+            if argument.name == template.search_argument {
+                Ok(complete_query(wi, &argument.value))
+            } else {
+                none
+            }
+        }
+        Reference::Tool { name } => match (name.as_str(), argument.name.as_str()) {
+            (SEARCH_TOOL, "query") => Ok(complete_query(wi, &argument.value)),
+            (FETCH_TOOL, "line") => Ok(complete_line(wi, &argument.value, 0)),
+            (SEARCH_TOOL | FETCH_TOOL, _) => none,
+            _ => Err(invalid_params(format!("Unknown tool: {}", name))),
+        },
+        Reference::Resource { uri } => match (uri.as_str(), argument.name.as_str()) {
+            (LINE_TEMPLATE, "n") | (RANGE_TEMPLATE, "start") => Ok(complete_line(wi, &argument.value, 0)),
+            (RANGE_TEMPLATE, "end") => {
+                let start = params
+                    .context
+                    .arguments
+                    .get("start")
+                    .and_then(|start| start.parse().ok())
+                    .unwrap_or(0);
+                Ok(complete_line(wi, &argument.value, start))
+            }
+            (LINE_TEMPLATE | RANGE_TEMPLATE, _) => none,
+            _ => Err(invalid_params(format!("Unknown resource template: {}", uri))),
+        },
+    }
+}
+
+fn invalid_params(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message,
+        data: None,
+    }
+}
+
+/// Registers the `completion/complete` method on `handler`.
+pub fn register(handler: &mut Handler, index: SharedIndex, templates: Vec<PromptTemplate>) {
+    handler.add_method_with_meta("completion/complete", move |params: Params, session: Meta| {
+        let wi = index.load_for(&session);
+        let templates = templates.clone();
+        async move {
+            log::debug!("RPC 'completion/complete' method called with params: {:?}", params);
+            let params = params.parse::<CompleteParams>()?;
+            let completion = complete(&wi, &templates, &params)?;
+            serde_json::to_value(CompleteResult { completion }).map_err(|e| {
+                log::error!("Failed to serialize CompleteResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::prompts::default_templates;
+    use crate::session::Session;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn test_index() -> WordIndex {
+        WordIndex::new("test_db.txt").expect("Failed to load test_db.txt")
+    }
+
+    #[test]
+    fn test_complete_query_ranks_by_document_frequency() {
+        let wi = test_index();
+        // "line" is on four lines, "lowercase" on one
+        let completion = complete_query(&wi, "l");
+        assert_eq!(completion.values, vec!["line", "lowercase"]);
+        assert_eq!(completion.total, 2);
+        assert!(!completion.has_more);
+
+        assert_eq!(complete_query(&wi, "test Re").values, vec!["test repeated"]);
+        assert_eq!(complete_query(&wi, "test ").values, Vec::<String>::new());
+
+        // Operators, quotes and parentheses stay in front of the completed word
+        assert_eq!(complete_query(&wi, "line -emp").values, vec!["line -empty"]);
+        assert_eq!(complete_query(&wi, "+emp").values, vec!["+empty"]);
+        assert_eq!(complete_query(&wi, "(\"emp").values, vec!["(\"empty"]);
+    }
+
+    #[test]
+    fn test_complete_line_numbers() {
+        let wi = test_index();
+        assert_eq!(complete_line(&wi, "", 0).total, wi.lines.len());
+        assert_eq!(complete_line(&wi, "1", 0).values, vec!["1"]);
+        assert_eq!(complete_line(&wi, "", 7).values, vec!["7", "8", "9"]);
+        assert_eq!(complete_line(&wi, "x", 0), Completion::default());
+        assert_eq!(complete_line(&wi, "0", 0).values, vec!["0"]);
+        assert_eq!(complete_line(&wi, "01", 0), Completion::default());
+    }
+
+    #[test]
+    fn test_line_ranges_are_generated_from_the_prefix() {
+        let lines = |typed, from, end| line_ranges(typed, from, end).into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(lines("1", 0, 25), [vec![1], (10..20).collect()].concat());
+        assert_eq!(lines("1", 15, 200), [(15..20).collect::<Vec<_>>(), (100..200).collect()].concat());
+        assert_eq!(lines("12", 0, 1250), [vec![12], (120..130).collect(), (1200..1250).collect()].concat());
+        assert!(lines("3", 4, 30).is_empty());
+        assert!(lines("99999999999999999999999", 0, usize::MAX).is_empty());
+
+        let huge = WordIndex::from_lines(vec![String::new(); 100_000]);
+        let completion = complete_line(&huge, "7", 0);
+        assert_eq!(completion.total, 1 + 10 + 100 + 1000 + 10_000);
+        assert_eq!(completion.values.len(), MAX_VALUES);
+        assert!(completion.has_more);
+    }
+
+    #[test]
+    fn test_completion_method_dispatches_on_reference() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, SharedIndex::new(test_index()), default_templates());
+        let complete = |params: Value| -> Value {
+            let request = json!({"jsonrpc": "2.0", "method": "completion/complete", "params": params, "id": 1});
+            let response = handler
+                .handle_request_sync(&request.to_string(), Arc::new(Session::stateless()))
+                .unwrap();
+            serde_json::from_str(&response).unwrap()
+        };
+
+        let response = complete(json!({
+            "ref": {"type": "ref/prompt", "name": "answer-from-records"},
+            "argument": {"name": "query", "value": "hel"}
+        }));
+        assert_eq!(response["result"]["completion"]["values"], json!(["hello"]));
+
+        let response = complete(json!({
+            "ref": {"type": "ref/tool", "name": "search"},
+            "argument": {"name": "query", "value": "punct"}
+        }));
+        assert_eq!(response["result"]["completion"]["values"], json!(["punctuation"]));
+
+        let response = complete(json!({
+            "ref": {"type": "ref/resource", "uri": "db://range/{start}-{end}"},
+            "argument": {"name": "end", "value": ""},
+            "context": {"arguments": {"start": "8"}}
+        }));
+        assert_eq!(response["result"]["completion"]["values"], json!(["8", "9"]));
+
+        let response = complete(json!({
+            "ref": {"type": "ref/prompt", "name": "nope"},
+            "argument": {"name": "query", "value": "x"}
+        }));
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidParams.code());
+    }
+}
@@ -0,0 +1,212 @@
+//! JSON-RPC conformance transcripts, replayed through every transport into the
+//! handler `main` serves.
+//!
+//! Each file in `conformance/` is a transcript of JSON lines, one client message
+//! per line: `{"send": <message or batch>}` or `{"send_raw": "<text>"}`, with
+//! `"expect"` holding the response the server must send, if it must send one.
+//! Expected responses match when every member they list is present and equal;
+//! arrays must match element by element.
+//!
+//! Over HTTP a step without `expect` must get `202 Accepted`, one whose message
+//! holds a request `200 OK`, and one holding no valid request `400 Bad Request`.
+
+use std::path::{Path, PathBuf};
+
+use jsonrpc_http_server::hyper::{self, header, Body, Request, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::lifecycle::Handler;
+use crate::message;
+use crate::session::SessionStore;
+use crate::streamable_http::{StreamableHttp, ENDPOINT_PATH, SESSION_ID_HEADER};
+use crate::watch::SharedIndex;
+use crate::{build_handler, plain_http, stdio, ServerOptions, WordIndex};
+
+const TRANSCRIPTS_DIR: &str = "conformance";
+
+#[derive(Deserialize, Debug)]
+struct Step {
+    #[serde(default)]
+    send: Option<Value>,
+    #[serde(default)]
+    send_raw: Option<String>,
+    #[serde(default)]
+    expect: Option<Value>,
+}
+
+impl Step {
+    fn body(&self) -> String {
+        match (&self.send, &self.send_raw) {
+            (_, Some(raw)) => raw.clone(),
+            (Some(message), None) => message.to_string(),
+            (None, None) => panic!("step sends nothing: {:?}", self),
+        }
+    }
+
+    fn expected_status(&self) -> StatusCode {
+        match &self.expect {
+            None => StatusCode::ACCEPTED,
+            Some(_) if message::split(&self.body()).has_requests => StatusCode::OK,
+            Some(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+fn transcripts() -> Vec<(PathBuf, Vec<Step>)> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(TRANSCRIPTS_DIR)
+        .expect("conformance transcripts directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no transcripts in {}", TRANSCRIPTS_DIR);
+    paths
+        .into_iter()
+        .map(|path| {
+            let steps = load(&path);
+            (path, steps)
+        })
+        .collect()
+}
+
+fn load(path: &Path) -> Vec<Step> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: bad step {}: {}", path.display(), line, e)))
+        .collect()
+}
+
+fn handler() -> Handler {
+    let index = SharedIndex::new(WordIndex::new("test_db.txt").expect("Failed to load test_db.txt"));
+    build_handler(index, ServerOptions::default())
+}
+
+/// Whether `actual` has every member of `expected`, recursively.
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|actual| matches(value, actual))),
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len() && expected.iter().zip(actual).all(|(e, a)| matches(e, a))
+        }
+        _ => expected == actual,
+    }
+}
+
+fn check(path: &Path, transport: &str, step: &Step, actual: Option<Value>) {
+    match (&step.expect, &actual) {
+        (None, None) => {}
+        (Some(expected), Some(actual)) if matches(expected, actual) => {}
+        _ => panic!(
+            "{} over {}: sent {}\nexpected {:?}\n     got {:?}",
+            path.display(),
+            transport,
+            step.body(),
+            step.expect,
+            actual
+        ),
+    }
+}
+
+fn parse(output: &str) -> Value {
+    serde_json::from_str(output).unwrap_or_else(|e| panic!("server sent invalid JSON {}: {}", output, e))
+}
+
+#[tokio::test]
+async fn test_transcripts_over_stdio() {
+    for (path, steps) in transcripts() {
+        let (mut client_in, server_in) = tokio::io::duplex(64 * 1024);
+        let (server_out, client_out) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(stdio::serve(handler(), SessionStore::default(), server_in, server_out));
+        let mut lines = BufReader::new(client_out).lines();
+
+        for (n, step) in steps.iter().enumerate() {
+            client_in.write_all(format!("{}\n", step.body()).as_bytes()).await.unwrap();
+            // A ping after a step that must go unanswered shows whether it was
+            let sentinel = json!(format!("sentinel-{}", n));
+            if step.expect.is_none() {
+                let ping = json!({"jsonrpc": "2.0", "id": sentinel, "method": "ping"});
+                client_in.write_all(format!("{}\n", ping).as_bytes()).await.unwrap();
+            }
+            let output = loop {
+                let line = lines.next_line().await.unwrap().expect("stdio output ended early");
+                let message = parse(&line);
+                // Skip notifications the server sends of its own accord
+                if message.get("method").is_none() {
+                    break message;
+                }
+            };
+            let actual = match step.expect {
+                None if output.get("id") == Some(&sentinel) => None,
+                _ => Some(output),
+            };
+            check(&path, "stdio", step, actual);
+        }
+
+        drop(client_in);
+        server.await.unwrap().expect("stdio should end cleanly at EOF");
+    }
+}
+
+fn post(body: String, session_id: Option<&str>) -> Request<Body> {
+    let mut builder = Request::post(ENDPOINT_PATH)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT, "application/json");
+    if let Some(id) = session_id {
+        builder = builder.header(SESSION_ID_HEADER, id);
+    }
+    builder.body(Body::from(body)).unwrap()
+}
+
+async fn read_response(
+    path: &Path,
+    transport: &str,
+    step: &Step,
+    response: hyper::Response<Body>,
+) -> Option<Value> {
+    let status = response.status();
+    assert_eq!(
+        status,
+        step.expected_status(),
+        "{} over {}: wrong status for {}",
+        path.display(),
+        transport,
+        step.body()
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    (!body.trim().is_empty()).then(|| parse(&body))
+}
+
+#[tokio::test]
+async fn test_transcripts_over_streamable_http() {
+    for (path, steps) in transcripts() {
+        let transport = StreamableHttp::new(handler(), SessionStore::default());
+        let mut session_id: Option<String> = None;
+        for step in &steps {
+            let response = transport.handle(post(step.body(), session_id.as_deref())).await;
+            if let Some(id) = response.headers().get(SESSION_ID_HEADER) {
+                session_id = Some(id.to_str().unwrap().to_owned());
+            }
+            let actual = read_response(&path, "streamable HTTP", step, response).await;
+            check(&path, "streamable HTTP", step, actual);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_transcripts_over_plain_http() {
+    for (path, steps) in transcripts() {
+        let handler = handler();
+        for step in &steps {
+            let response = plain_http::handle_post(&handler, post(step.body(), None)).await;
+            let actual = read_response(&path, "plain HTTP", step, response).await;
+            check(&path, "plain HTTP", step, actual);
+        }
+    }
+}
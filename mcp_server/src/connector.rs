@@ -0,0 +1,260 @@
+//! The `search`/`fetch` contract of deep-research style connector hosts, enabled
+//! with `--tool-contract deep-research`.
+//!
+//! In this mode `search(query)` returns `{results: [{id, title, url}]}`, best
+//! matches first, and `fetch(id)` returns `{id, title, text, url, metadata}`,
+//! both as structured content and as JSON text. Ids are `line-{n}` as in the
+//! regular tools; titles are synthesized from the line text and URLs from the
+//! `db://line/{n}` resource URI, or from `--connector-base-url` when given.
+
+use jsonrpc_http_server::jsonrpc_core::{Error, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::cancellation;
+use crate::progress::Progress;
+use crate::resources::DbResource;
+use crate::tools::{search_failed, CallToolResult, Hit, Tool, ToolAnnotations, FETCH_TOOL, SEARCH_TOOL};
+use crate::{SearchOptions, WordIndex};
+
+/// Characters of line text used in a synthesized title.
+const TITLE_CHARS: usize = 60;
+
+#[derive(Clone, Debug, Default)]
+pub struct Connector {
+    /// Prefix of document URLs, followed by the line number; `db://line/` if unset.
+    pub base_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchArguments {
+    query: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct FetchArguments {
+    id: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SearchResult {
+    id: String,
+    title: String,
+    url: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Document {
+    id: String,
+    title: String,
+    text: String,
+    url: String,
+    metadata: Value,
+}
+
+/// The line number in an id made by [`Hit::id_for`].
+pub fn parse_id(id: &str) -> Option<usize> {
+    id.strip_prefix("line-")?.parse().ok()
+}
+
+fn title(line: usize, text: &str) -> String {
+    let mut preview: String = text.trim().chars().take(TITLE_CHARS).collect();
+    if text.trim().chars().count() > TITLE_CHARS {
+        preview.push('…');
+    }
+    if preview.is_empty() {
+        format!("Line {}", line)
+    } else {
+        format!("Line {}: {}", line, preview)
+    }
+}
+
+impl Connector {
+    fn url(&self, line: usize) -> String {
+        match &self.base_url {
+            Some(base) => format!("{}{}", base, line),
+            None => DbResource::line_uri(line),
+        }
+    }
+
+    /// The search and fetch tools under this contract.
+    pub fn tools(&self) -> Vec<Tool> {
+        vec![
+            Tool {
+                name: SEARCH_TOOL.into(),
+                description: "Search the database for records containing all of the given words. Returns ids to pass to fetch.".into(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "query": {"type": "string", "description": "Whitespace-separated words that must all appear in a record. Quote words to match them as an exact phrase; use OR, NOT or -term, and parentheses to combine terms, * or ? as wildcards within words, and word~ to allow typos."}
+                    },
+                    "required": ["query"]
+                }),
+                output_schema: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "results": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "id": {"type": "string"},
+                                    "title": {"type": "string"},
+                                    "url": {"type": "string"}
+                                },
+                                "required": ["id", "title", "url"]
+                            }
+                        }
+                    },
+                    "required": ["results"]
+                })),
+                annotations: ToolAnnotations::read_only("Search records"),
+            },
+            Tool {
+                name: FETCH_TOOL.into(),
+                description: "Fetch the full text of a record by the id returned from search.".into(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "description": "Record id, as returned by search."}
+                    },
+                    "required": ["id"]
+                }),
+                output_schema: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string"},
+                        "title": {"type": "string"},
+                        "text": {"type": "string"},
+                        "url": {"type": "string"},
+                        "metadata": {"type": "object"}
+                    },
+                    "required": ["id", "title", "text", "url"]
+                })),
+                annotations: ToolAnnotations::read_only("Fetch record"),
+            },
+        ]
+    }
+
+    /// Runs one of [`tools`](Self::tools). Returns `None` for any other tool name.
+    pub fn call_tool(
+        &self,
+        wi: &WordIndex,
+        name: &str,
+        arguments: Value,
+        options: &SearchOptions,
+        progress: &mut Progress,
+    ) -> Option<Result<CallToolResult, Error>> {
+        let result = match name {
+            SEARCH_TOOL => match serde_json::from_value::<SearchArguments>(arguments) {
+                Ok(args) => self.search(wi, &args.query, options, progress),
+                Err(e) => Ok(CallToolResult::error(format!("Invalid arguments for search: {}", e))),
+            },
+            FETCH_TOOL => Ok(match serde_json::from_value::<FetchArguments>(arguments) {
+                Ok(args) => self.fetch(wi, &args.id),
+                Err(e) => CallToolResult::error(format!("Invalid arguments for fetch: {}", e)),
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn search(
+        &self,
+        wi: &WordIndex,
+        query: &str,
+        options: &SearchOptions,
+        progress: &mut Progress,
+    ) -> Result<CallToolResult, Error> {
+        let search = wi.search_ranked(query, &cancellation::current(), options, |done, total| {
+            progress.report(done, total, format!("Searched {} of {} word occurrences", done, total))
+        });
+        let hits = match search {
+            Ok(hits) => hits,
+            Err(e) => return search_failed(e),
+        };
+        let results: Vec<SearchResult> = hits
+            .into_iter()
+            .map(|hit| hit.line)
+            .filter_map(|line| {
+                wi.lines.get(line).map(|text| SearchResult {
+                    id: Hit::id_for(line),
+                    title: title(line, text),
+                    url: self.url(line),
+                })
+            })
+            .collect();
+        Ok(structured(json!({ "results": results })))
+    }
+
+    fn fetch(&self, wi: &WordIndex, id: &str) -> CallToolResult {
+        let Some((line, text)) = parse_id(id).and_then(|line| wi.fetch(line).map(|text| (line, text))) else {
+            return CallToolResult::error(format!("Unknown record id: {}", id));
+        };
+        structured(json!(Document {
+            id: id.into(),
+            title: title(line, &text),
+            text,
+            url: self.url(line),
+            metadata: json!({ "line": line, "uri": DbResource::line_uri(line) }),
+        }))
+    }
+}
+
+/// The result as structured content, and as JSON text for hosts that parse it.
+fn structured(value: Value) -> CallToolResult {
+    CallToolResult::structured(value.to_string(), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(connector: &Connector, name: &str, arguments: Value) -> CallToolResult {
+        let wi = WordIndex::new("test_db.txt").unwrap();
+        connector
+            .call_tool(&wi, name, arguments, &SearchOptions::default(), &mut Progress::default())
+            .expect("connector tool")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_search_returns_ids_titles_and_urls() {
+        let result = call(&Connector::default(), SEARCH_TOOL, json!({"query": "hello"}));
+        let expected = json!({"results": [{"id": "line-0", "title": "Line 0: Hello world!", "url": "db://line/0"}]});
+        assert_eq!(result.structured_content, Some(expected.clone()));
+        let text = serde_json::to_value(&result.content[0]).unwrap()["text"].as_str().unwrap().to_owned();
+        assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), expected);
+
+        let result = call(&Connector::default(), SEARCH_TOOL, json!({"query": "(hello"}));
+        assert!(result.is_error);
+    }
+
+    #[test]
+    fn test_fetch_by_id() {
+        let connector = Connector {
+            base_url: Some("https://records.example/".into()),
+        };
+        let result = call(&connector, FETCH_TOOL, json!({"id": "line-1"}));
+        assert_eq!(
+            result.structured_content,
+            Some(json!({
+                "id": "line-1",
+                "title": "Line 1: This is a test line.",
+                "text": "This is a test line.",
+                "url": "https://records.example/1",
+                "metadata": {"line": 1, "uri": "db://line/1"}
+            }))
+        );
+
+        assert!(call(&connector, FETCH_TOOL, json!({"id": "line-99"})).is_error);
+        assert!(call(&connector, FETCH_TOOL, json!({"id": "1"})).is_error);
+    }
+
+    #[test]
+    fn test_titles_are_shortened() {
+        assert_eq!(title(3, ""), "Line 3");
+        let long = "word ".repeat(20);
+        assert!(title(0, &long).ends_with('…'));
+    }
+}
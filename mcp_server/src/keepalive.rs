@@ -0,0 +1,132 @@
+//! `ping` in both directions: the handler answering client pings, and optional
+//! server-initiated pings that tear down sessions whose client stopped answering.
+//!
+//! Only sessions with a stream for server-initiated messages are pinged: stdio,
+//! and Streamable HTTP clients holding the GET stream open. Any other session,
+//! including one that never finished initializing, is torn down once its client
+//! has sent nothing for as long as a ping may take to go out and time out.
+
+use std::time::Duration;
+
+use jsonrpc_http_server::jsonrpc_core::Params;
+use serde_json::json;
+
+use crate::lifecycle::{self, Handler};
+use crate::session::{PingDue, SessionStore};
+
+/// Registers the `ping` method on `handler`.
+pub fn register(handler: &mut Handler) {
+    handler.add_method(lifecycle::PING, |params: Params| async move {
+        log::debug!("RPC 'ping' method called with params: {:?}", params);
+        Ok(json!({}))
+    });
+}
+
+/// Pings each pingable session every `interval`, removing it from `sessions`
+/// when a ping goes unanswered for `timeout`. Sessions that cannot be pinged
+/// are removed once idle for `interval + timeout`.
+pub fn check(sessions: &SessionStore, interval: Duration, timeout: Duration) {
+    for session in sessions.all() {
+        if !session.is_operating() || !session.has_standalone() {
+            if session.idle_for().is_some_and(|idle| idle >= interval + timeout) {
+                log::warn!("Session {} was idle for {:?}, closing it.", session.id, interval + timeout);
+                sessions.remove(&session.id);
+            }
+            continue;
+        }
+        match session.ping_due(interval, timeout) {
+            PingDue::No => {}
+            PingDue::Yes => session.send_ping(),
+            PingDue::TimedOut => {
+                log::warn!("Session {} did not answer ping within {:?}, closing it.", session.id, timeout);
+                sessions.remove(&session.id);
+            }
+        }
+    }
+}
+
+/// Runs [`check`] forever, often enough to notice both due pings and timeouts.
+pub async fn run(sessions: SessionStore, interval: Duration, timeout: Duration) {
+    let mut ticker = tokio::time::interval(interval.min(timeout).max(Duration::from_millis(100)));
+    loop {
+        ticker.tick().await;
+        check(&sessions, interval, timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::is_response;
+    use crate::session::Session;
+    use jsonrpc_http_server::jsonrpc_core::{Id, Value};
+    use std::sync::Arc;
+
+    fn operating_session(sessions: &SessionStore) -> Arc<Session> {
+        let session = Arc::new(Session::new("pinged".into()));
+        session.initialize("2025-06-18", Value::Null).unwrap();
+        session.mark_initialized();
+        sessions.register(Arc::clone(&session));
+        session
+    }
+
+    #[test]
+    fn test_ping_method_answers_before_initialize() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler);
+        let response = handler
+            .handle_request_sync(
+                r#"{"jsonrpc": "2.0", "method": "ping", "id": 1}"#,
+                Arc::new(Session::new("new".into())),
+            )
+            .unwrap();
+        assert_eq!(response, r#"{"jsonrpc":"2.0","result":{},"id":1}"#);
+    }
+
+    #[test]
+    fn test_check_pings_and_expires_sessions() {
+        let sessions = SessionStore::default();
+        let session = operating_session(&sessions);
+        let mut events = session.attach_standalone();
+        let long = Duration::from_secs(60);
+
+        check(&sessions, Duration::ZERO, long);
+        let ping: Value = serde_json::from_str(&events.try_recv().unwrap().data).unwrap();
+        assert_eq!(ping["method"], "ping");
+
+        check(&sessions, Duration::ZERO, long);
+        assert!(events.try_recv().is_err(), "no second ping while one is outstanding");
+
+        let pong = json!({"jsonrpc": "2.0", "id": ping["id"], "result": {}});
+        assert!(is_response(&pong));
+        assert!(session.handle_response(&pong));
+        check(&sessions, long, long);
+        assert!(events.try_recv().is_err(), "next ping waits for the interval");
+        check(&sessions, Duration::ZERO, long);
+        assert!(events.try_recv().is_ok());
+
+        check(&sessions, long, Duration::ZERO);
+        assert!(sessions.get("pinged").is_none(), "unanswered session is removed");
+        assert!(!session.is_operating());
+    }
+
+    #[test]
+    fn test_check_expires_idle_sessions_without_a_stream() {
+        let sessions = SessionStore::default();
+        let session = operating_session(&sessions);
+        let long = Duration::from_secs(60);
+
+        check(&sessions, long, long);
+        assert!(sessions.get("pinged").is_some(), "recently active session is kept");
+        assert!(session.idle_for().is_some());
+
+        session.track_request(Id::Num(1), Default::default(), None);
+        check(&sessions, Duration::ZERO, Duration::ZERO);
+        assert!(sessions.get("pinged").is_some(), "a request is still being handled");
+
+        session.finish_request(&Id::Num(1));
+        check(&sessions, Duration::ZERO, Duration::ZERO);
+        assert!(sessions.get("pinged").is_none(), "idle session is removed");
+        assert!(!session.is_operating());
+    }
+}
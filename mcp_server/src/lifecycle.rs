@@ -0,0 +1,112 @@
+//! The MCP session lifecycle: `initialize` → `notifications/initialized` →
+//! normal operation → shutdown, enforced for every call by [`LifecycleGate`].
+
+use jsonrpc_http_server::jsonrpc_core::futures_util::future::{self, Either};
+use jsonrpc_http_server::jsonrpc_core::middleware::{Middleware, NoopCallFuture, NoopFuture};
+use jsonrpc_http_server::jsonrpc_core::{Call, Error, ErrorCode, Failure, MetaIoHandler, Output};
+
+use crate::cancellation::RequestTracker;
+use crate::session::Meta;
+
+/// The JSON-RPC handler every transport drives, with the lifecycle enforced and
+/// requests tracked for cancellation.
+pub type Handler = MetaIoHandler<Meta, (LifecycleGate, RequestTracker)>;
+
+/// Creates a handler with no methods registered yet.
+pub fn new_handler() -> Handler {
+    Handler::with_middleware((LifecycleGate, RequestTracker))
+}
+
+pub const INITIALIZE: &str = "initialize";
+pub const INITIALIZED_NOTIFICATION: &str = "notifications/initialized";
+pub const PING: &str = "ping";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Nothing but `initialize` is accepted yet.
+    AwaitingInitialize,
+    /// `initialize` succeeded; waiting for the client's `notifications/initialized`,
+    /// the only other message accepted besides pings.
+    AwaitingInitialized,
+    Operating,
+    /// The transport closed or the session was terminated.
+    ShutDown,
+}
+
+/// Checks whether `method` may be called in `phase`.
+pub fn check_call(phase: Phase, method: &str) -> Result<(), Error> {
+    let rejection = match phase {
+        Phase::ShutDown => "Session has been shut down.",
+        // Pings are allowed at any point, even before initialization
+        _ if method == PING => return Ok(()),
+        Phase::AwaitingInitialize if method == INITIALIZE => return Ok(()),
+        Phase::AwaitingInitialize => "Server not initialized: call 'initialize' first.",
+        Phase::AwaitingInitialized | Phase::Operating if method == INITIALIZE => {
+            "Session is already initialized."
+        }
+        Phase::AwaitingInitialized if method == INITIALIZED_NOTIFICATION => return Ok(()),
+        Phase::AwaitingInitialized => "Server not initialized: send 'notifications/initialized' first.",
+        Phase::Operating => return Ok(()),
+    };
+    Err(Error {
+        code: ErrorCode::InvalidRequest,
+        message: rejection.into(),
+        data: None,
+    })
+}
+
+/// Middleware rejecting calls that the session's lifecycle phase does not allow.
+#[derive(Clone, Debug, Default)]
+pub struct LifecycleGate;
+
+impl Middleware<Meta> for LifecycleGate {
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: Call, session: Meta, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, Meta) -> X + Send + Sync,
+        X: std::future::Future<Output = Option<Output>> + Send + 'static,
+    {
+        let method = match &call {
+            Call::MethodCall(method_call) => method_call.method.as_str(),
+            Call::Notification(notification) => notification.method.as_str(),
+            Call::Invalid { .. } => return Either::Right(next(call, session)),
+        };
+        let error = match session.check_call(method) {
+            Ok(()) => return Either::Right(next(call, session)),
+            Err(error) => error,
+        };
+        log::warn!("Session {} rejected '{}': {}", session.id, method, error.message);
+        let output = match call {
+            Call::MethodCall(method_call) => Some(Output::Failure(Failure {
+                jsonrpc: method_call.jsonrpc,
+                error,
+                id: method_call.id,
+            })),
+            _ => None,
+        };
+        Either::Left(Box::pin(future::ready(output)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_call_follows_phases() {
+        assert!(check_call(Phase::AwaitingInitialize, INITIALIZE).is_ok());
+        assert!(check_call(Phase::AwaitingInitialize, "tools/list").is_err());
+        assert!(check_call(Phase::AwaitingInitialize, PING).is_ok());
+        assert!(check_call(Phase::AwaitingInitialized, INITIALIZED_NOTIFICATION).is_ok());
+        assert!(check_call(Phase::AwaitingInitialized, INITIALIZE).is_err());
+        assert!(check_call(Phase::AwaitingInitialized, "tools/call").is_err());
+        assert!(check_call(Phase::AwaitingInitialized, "resources/read").is_err());
+        assert!(check_call(Phase::AwaitingInitialized, PING).is_ok());
+        assert!(check_call(Phase::Operating, "tools/list").is_ok());
+        assert!(check_call(Phase::Operating, INITIALIZE).is_err());
+        assert!(check_call(Phase::ShutDown, "tools/list").is_err());
+        assert!(check_call(Phase::ShutDown, PING).is_err());
+    }
+}
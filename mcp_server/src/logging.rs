@@ -0,0 +1,308 @@
+//! MCP logging: server log records forwarded to clients as `notifications/message`.
+//!
+//! [`init`] installs a `log` backend that writes to stderr through env_logger as
+//! before, and additionally queues records for the session that asked for them
+//! with `logging/setLevel`. A session only hears about records raised while one
+//! of its own requests was being handled, as told by the request context
+//! [`cancellation`] keeps, and session ids are blanked out of what it hears.
+//! Queueing never blocks; a background task drains the queue and sends the
+//! notifications, and records arriving while the queue is full are dropped.
+//!
+//! Records are only formatted for clients down to the most verbose level a
+//! session asked for, which also sets `log`'s global maximum level together with
+//! the stderr filter. The level is recomputed whenever a session sets its level
+//! or goes away.
+//!
+//! Only records from this crate are forwarded, and never `trace` ones: MCP has no
+//! such level, and the transports trace every message they write, which would
+//! otherwise feed back into the queue.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use jsonrpc_http_server::jsonrpc_core::{Params, Value};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::cancellation;
+use crate::lifecycle::Handler;
+use crate::session::{Meta, SessionStore};
+
+pub const MESSAGE_NOTIFICATION: &str = "notifications/message";
+
+/// What session ids are replaced with in forwarded records.
+const REDACTED_SESSION: &str = "<session>";
+
+/// Records queued for clients and not yet delivered, past which more are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The stderr logger's filter, as a `LevelFilter` discriminant.
+static STDERR_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Off as usize);
+
+/// The most verbose level any session asked for, as a `LevelFilter` discriminant.
+static CLIENT_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Off as usize);
+
+fn level_filter(value: usize) -> LevelFilter {
+    LevelFilter::iter().nth(value).unwrap_or(LevelFilter::Off)
+}
+
+fn client_level() -> LevelFilter {
+    level_filter(CLIENT_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Sets the level records are formatted for clients down to, and `log`'s
+/// maximum level with it.
+fn set_client_level(level: LevelFilter) {
+    if CLIENT_LEVEL.swap(level as usize, Ordering::Relaxed) != level as usize {
+        log::set_max_level(level.max(level_filter(STDERR_LEVEL.load(Ordering::Relaxed))));
+    }
+}
+
+/// The most verbose level a session in `sessions` asks for.
+fn requested_level(sessions: &SessionStore) -> LevelFilter {
+    sessions
+        .all()
+        .iter()
+        .filter_map(|session| session.log_level())
+        .min()
+        .map_or(LevelFilter::Off, LoggingLevel::to_filter)
+}
+
+/// Sets the client level to the most verbose one a session in `sessions` asks
+/// for.
+pub fn refresh_client_level(sessions: &SessionStore) {
+    set_client_level(requested_level(sessions));
+}
+
+fn forwardable(metadata: &Metadata) -> bool {
+    metadata.level() <= client_level()
+        && metadata.level() <= Level::Debug
+        && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+}
+
+/// Syslog severities used by MCP, least severe first.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LoggingLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl LoggingLevel {
+    /// The MCP level for a `log` level, or `None` for `trace`, which is not forwarded.
+    pub fn from_log(level: Level) -> Option<Self> {
+        match level {
+            Level::Error => Some(LoggingLevel::Error),
+            Level::Warn => Some(LoggingLevel::Warning),
+            Level::Info => Some(LoggingLevel::Info),
+            Level::Debug => Some(LoggingLevel::Debug),
+            Level::Trace => None,
+        }
+    }
+
+    /// The most verbose `log` level holding records at this level or above.
+    fn to_filter(self) -> LevelFilter {
+        match self {
+            LoggingLevel::Debug => LevelFilter::Debug,
+            LoggingLevel::Info | LoggingLevel::Notice => LevelFilter::Info,
+            LoggingLevel::Warning => LevelFilter::Warn,
+            _ => LevelFilter::Error,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SetLevelParams {
+    level: LoggingLevel,
+}
+
+/// The params of a `notifications/message`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LogMessage {
+    pub level: LoggingLevel,
+    pub logger: String,
+    pub data: String,
+}
+
+impl LogMessage {
+    fn from_record(record: &Record) -> Option<Self> {
+        if !forwardable(record.metadata()) {
+            return None;
+        }
+        Some(LogMessage {
+            level: LoggingLevel::from_log(record.level())?,
+            logger: record.target().into(),
+            data: record.args().to_string(),
+        })
+    }
+}
+
+struct ClientLogger {
+    stderr: env_logger::Logger,
+    /// Records by the id of the session whose request raised them.
+    queue: mpsc::Sender<(String, LogMessage)>,
+}
+
+impl Log for ClientLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || forwardable(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) {
+            self.stderr.log(record);
+        }
+        let Some(session) = cancellation::current_session() else {
+            return;
+        };
+        if let Some(message) = LogMessage::from_record(record) {
+            // Dropped if the queue is full, or when the runtime shuts down.
+            let _ = self.queue.try_send((session, message));
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+    }
+}
+
+/// Installs the logger built by `stderr` as the global logger, extended to
+/// forward records to the sessions in `sessions`. Must be called from within the
+/// tokio runtime.
+pub fn init(mut stderr: env_logger::Builder, sessions: SessionStore) -> Result<(), log::SetLoggerError> {
+    let stderr = stderr.build();
+    STDERR_LEVEL.store(stderr.filter() as usize, Ordering::Relaxed);
+    let max_level = stderr.filter().max(client_level());
+    let (queue, mut messages) = mpsc::channel(QUEUE_CAPACITY);
+    log::set_boxed_logger(Box::new(ClientLogger { stderr, queue }))?;
+    log::set_max_level(max_level);
+    tokio::spawn(async move {
+        while let Some((session, message)) = messages.recv().await {
+            deliver(&sessions, &session, message);
+        }
+    });
+    Ok(())
+}
+
+/// Sends `message`, raised while handling a request of session `session_id`,
+/// to that session if its requested level admits it, with the ids of every
+/// session in `sessions` blanked out.
+pub fn deliver(sessions: &SessionStore, session_id: &str, mut message: LogMessage) {
+    let Some(session) = sessions.get(session_id).filter(|session| session.wants_log(message.level)) else {
+        return;
+    };
+    for other in sessions.all() {
+        message.data = message.data.replace(&other.id, REDACTED_SESSION);
+    }
+    session.notify(MESSAGE_NOTIFICATION, serde_json::to_value(&message).unwrap_or(Value::Null));
+}
+
+/// Registers the `logging/setLevel` method on `handler`, for the sessions in
+/// `sessions`.
+pub fn register(handler: &mut Handler, sessions: SessionStore) {
+    handler.add_method_with_meta("logging/setLevel", move |params: Params, session: Meta| {
+        let sessions = sessions.clone();
+        async move {
+            log::debug!("RPC 'logging/setLevel' method called with params: {:?}", params);
+            let params = params.parse::<SetLevelParams>()?;
+            session.set_log_level(params.level);
+            refresh_client_level(&sessions);
+            log::info!("Session {} set log level to {:?}", session.id, params.level);
+            Ok(serde_json::json!({}))
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::session::Session;
+    use std::sync::Arc;
+
+    fn message(level: LoggingLevel) -> LogMessage {
+        LogMessage {
+            level,
+            logger: "mcp_server::admin".into(),
+            data: "Session logged reindexed the database, as did session other.".into(),
+        }
+    }
+
+    fn operating_session(sessions: &SessionStore, id: &str) -> Arc<Session> {
+        let session = Arc::new(Session::new(id.into()));
+        session.initialize("2025-06-18", Value::Null).unwrap();
+        session.mark_initialized();
+        sessions.register(Arc::clone(&session));
+        session
+    }
+
+    #[test]
+    fn test_level_mapping_and_order() {
+        assert_eq!(LoggingLevel::from_log(Level::Warn), Some(LoggingLevel::Warning));
+        assert_eq!(LoggingLevel::from_log(Level::Trace), None);
+        assert!(LoggingLevel::Emergency > LoggingLevel::Error);
+        assert!(LoggingLevel::Debug < LoggingLevel::Info);
+        assert_eq!(serde_json::to_value(LoggingLevel::Warning).unwrap(), "warning");
+        assert_eq!(LoggingLevel::Notice.to_filter(), LevelFilter::Info);
+        assert_eq!(LoggingLevel::Alert.to_filter(), LevelFilter::Error);
+    }
+
+    #[test]
+    fn test_set_level_filters_delivered_messages() {
+        let mut handler = lifecycle::new_handler();
+        let sessions = SessionStore::default();
+        register(&mut handler, sessions.clone());
+        let session = operating_session(&sessions, "logged");
+        let mut events = session.attach_standalone();
+        let other = operating_session(&sessions, "other");
+        other.set_log_level(LoggingLevel::Debug);
+        let mut other_events = other.attach_standalone();
+
+        deliver(&sessions, "logged", message(LoggingLevel::Error));
+        assert!(events.try_recv().is_err(), "nothing is forwarded before setLevel");
+        assert!(other_events.try_recv().is_err(), "records go only to the session that raised them");
+
+        let response = handler
+            .handle_request_sync(
+                r#"{"jsonrpc": "2.0", "method": "logging/setLevel", "params": {"level": "warning"}, "id": 1}"#,
+                Arc::clone(&session),
+            )
+            .unwrap();
+        assert_eq!(response, r#"{"jsonrpc":"2.0","result":{},"id":1}"#);
+
+        deliver(&sessions, "logged", message(LoggingLevel::Info));
+        deliver(&sessions, "logged", message(LoggingLevel::Error));
+        let event = events.try_recv().unwrap();
+        let notification: Value = serde_json::from_str(&event.data).unwrap();
+        assert_eq!(notification["method"], MESSAGE_NOTIFICATION);
+        assert_eq!(notification["params"]["level"], "error");
+        assert_eq!(notification["params"]["logger"], "mcp_server::admin");
+        assert_eq!(
+            notification["params"]["data"],
+            "Session <session> reindexed the database, as did session <session>."
+        );
+        assert!(events.try_recv().is_err(), "info is below the requested level");
+        assert!(other_events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_requested_level_follows_sessions() {
+        let sessions = SessionStore::default();
+        let session = operating_session(&sessions, "verbose");
+        assert_eq!(requested_level(&sessions), LevelFilter::Off);
+        session.set_log_level(LoggingLevel::Debug);
+        operating_session(&sessions, "quiet").set_log_level(LoggingLevel::Warning);
+        assert_eq!(requested_level(&sessions), LevelFilter::Debug);
+
+        sessions.remove("verbose");
+        assert_eq!(requested_level(&sessions), LevelFilter::Warn, "records are formatted down to the requested level");
+        sessions.remove("quiet");
+        assert_eq!(requested_level(&sessions), LevelFilter::Off, "no session is left asking for records");
+    }
+}
@@ -3,13 +3,42 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
+use cancellation::CancellationToken;
 use clap::Parser;
-use env_logger; // Added env_logger
-use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, IoHandler, Params, Value};
-use jsonrpc_http_server::{DomainsValidation, ServerBuilder};
-use log; // Added log
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Params, Value};
+use jsonrpc_http_server::{hyper, DomainsValidation, ServerBuilder};
+use lifecycle::Handler;
+use query::{Query, SearchError};
+use ranking::{Bm25, ScoredLine, Sort};
 use serde::{Deserialize, Serialize}; // Added for InitializeParams/Result
+use session::{Meta, Session, SessionStore};
+use streamable_http::StreamableHttp;
+use watch::{DbWatcher, SharedIndex, ToolsWatcher};
+
+mod admin;
+mod cancellation;
+mod completion;
+#[cfg(test)]
+mod conformance;
+mod connector;
+mod keepalive;
+mod lifecycle;
+mod logging;
+mod message;
+mod plain_http;
+mod progress;
+mod prompts;
+mod query;
+mod ranking;
+mod resources;
+mod roots;
+mod session;
+mod stdio;
+mod streamable_http;
+mod tools;
+mod watch;
 
 // Structs for the 'initialize' RPC method
 #[derive(Deserialize, Debug)]
@@ -27,125 +56,531 @@ struct InitializeParams {
     client_info: Option<ClientInfo>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct ToolCapabilities {
     list_changed: bool,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ResourceCapabilities {
+    subscribe: bool,
+    list_changed: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct PromptCapabilities {
+    list_changed: bool,
+}
+
+/// A capability with no options, serialized as `{}`.
+#[derive(Serialize, Debug, Clone)]
+struct Enabled {}
+
+// Non-standard capabilities from before tools/list existed, kept for --compat-legacy-capabilities
+#[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct SearchCapabilities {
     enabled: bool,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct FetchCapabilities {
     enabled: bool,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 struct ServerCapabilities {
-    tools: ToolCapabilities,
-    search: SearchCapabilities,
-    fetch: FetchCapabilities,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<ToolCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resources: Option<ResourceCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompts: Option<PromptCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logging: Option<Enabled>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completions: Option<Enabled>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search: Option<SearchCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fetch: Option<FetchCapabilities>,
+}
+
+impl ServerCapabilities {
+    /// Advertises exactly the features whose methods are registered on `handler`.
+    ///
+    /// With `legacy` set, the capabilities also take the pre-MCP-tools shape our
+    /// older clients expect: `search` and `fetch` keys and `tools.listChanged: true`.
+    fn implemented_by(handler: &Handler, options: &ServerOptions) -> Self {
+        let has = |method: &str| handler.iter().any(|(name, _)| name == method);
+        let mut capabilities = ServerCapabilities {
+            tools: has("tools/list").then(|| ToolCapabilities {
+                list_changed: options.watch_tools || has(admin::ADD_TOOL),
+            }),
+            resources: has("resources/list").then(|| ResourceCapabilities {
+                subscribe: has("resources/subscribe"),
+                list_changed: options.watch_db,
+            }),
+            prompts: has("prompts/list").then_some(PromptCapabilities { list_changed: false }),
+            logging: has("logging/setLevel").then_some(Enabled {}),
+            completions: has("completion/complete").then_some(Enabled {}),
+            search: None,
+            fetch: None,
+        };
+        if options.compat_legacy_capabilities {
+            capabilities.tools = Some(ToolCapabilities { list_changed: true });
+            capabilities.search = Some(SearchCapabilities { enabled: true });
+            capabilities.fetch = Some(FetchCapabilities { enabled: true });
+        }
+        capabilities
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ServerInfo {
+    name: String,
+    version: String,
+}
+
+impl ServerInfo {
+    fn from_cargo() -> Self {
+        ServerInfo {
+            name: env!("CARGO_PKG_NAME").into(),
+            version: env!("CARGO_PKG_VERSION").into(),
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct InitializeResult {
+    protocol_version: String,
     capabilities: ServerCapabilities,
+    server_info: ServerInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instructions: Option<String>,
+}
+
+/// MCP protocol revisions this server implements, newest first.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Picks the protocol version for a session from the one the client asked for.
+///
+/// Clients that do not send a version get the newest one we support; clients that
+/// ask for a version we do not implement are rejected with the list we do.
+fn negotiate_protocol_version(requested: Option<&str>) -> Result<&'static str, Error> {
+    match requested {
+        None => Ok(SUPPORTED_PROTOCOL_VERSIONS[0]),
+        Some(requested) => SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .find(|supported| **supported == requested)
+            .copied()
+            .ok_or_else(|| Error {
+                code: ErrorCode::InvalidParams,
+                message: "Unsupported protocol version".into(),
+                data: Some(serde_json::json!({
+                    "supported": SUPPORTED_PROTOCOL_VERSIONS,
+                    "requested": requested,
+                })),
+            }),
+    }
+}
+
+/// Settings from the command line that shape the server's responses.
+#[derive(Clone, Debug, Default)]
+struct ServerOptions {
+    /// Free-form usage hints returned to clients in `initialize`.
+    instructions: Option<String>,
+    /// Advertise the old non-standard capability shape alongside the MCP one.
+    compat_legacy_capabilities: bool,
+    /// Whether the database file is watched, so resource changes are announced.
+    watch_db: bool,
+    /// Prompt templates to serve; prompts are not offered at all when empty.
+    prompts: Vec<prompts::PromptTemplate>,
+    /// What the admin methods act on; they are not registered when unset.
+    admin: Option<admin::AdminContext>,
+    /// The tools served, including saved searches added at runtime.
+    tools: tools::ToolRegistry,
+    /// Whether a saved searches file is watched, so tool list changes are announced.
+    watch_tools: bool,
+    /// Which client roots sessions serve instead of db.txt.
+    roots: roots::RootsPolicy,
+    /// The sessions being served, which `logging/setLevel` looks across.
+    sessions: SessionStore,
+}
+
+/// Line numbers intersected between checks for cancellation during a search.
+const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+/// Lines indexed between progress reports while building a [`WordIndex`].
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// How searches are run, beyond the query itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchOptions {
+    /// Parameters for ranking hits.
+    pub bm25: Bm25,
+    /// Most indexed words a pattern or fuzzy word expands to, taken in
+    /// dictionary order or closest first. Words past the cap are not searched for.
+    pub max_expansions: usize,
+    /// Whether a plain word that is not indexed matches the words near it, as
+    /// if written `word~`.
+    pub auto_fuzzy: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            bm25: Bm25::default(),
+            max_expansions: 256,
+            auto_fuzzy: false,
+        }
+    }
+}
+
+/// An occurrence of a word: the line, and the word's position among the line's words.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Posting {
+    pub line: usize,
+    pub position: usize,
 }
 
 #[derive(Debug)]
 pub struct WordIndex {
     pub lines: Vec<String>,
-    pub index: HashMap<String, Vec<usize>>,
+    /// Every occurrence of each word, in line and position order. How often a
+    /// word occurs on a line is the number of its postings there.
+    pub index: HashMap<String, Vec<Posting>>,
+    /// Every indexed word, sorted, for looking words up by prefix.
+    pub terms: Vec<String>,
+    /// Number of words on each line.
+    pub line_lengths: Vec<usize>,
+    /// Mean of `line_lengths`, used to normalize lengths when ranking.
+    pub average_line_length: f64,
 }
 
 impl WordIndex {
     pub fn new(filename: &str) -> Result<Self, std::io::Error> {
+        Self::new_with_progress(filename, |_, _| {})
+    }
+
+    /// Like [`new`](Self::new), calling `on_progress(lines_indexed, total_lines)`
+    /// every [`PROGRESS_INTERVAL`] lines and once at the end.
+    pub fn new_with_progress(
+        filename: &str,
+        on_progress: impl FnMut(usize, usize),
+    ) -> Result<Self, std::io::Error> {
         log::debug!("WordIndex::new called with filename: {}", filename);
         let path = Path::new(filename);
         let file = File::open(path)?;
         let reader = BufReader::new(file);
+        let raw_lines = reader.lines().collect::<Result<Vec<String>, _>>()?;
+        Ok(Self::build(raw_lines, on_progress))
+    }
 
-        let mut lines = Vec::new();
-        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
-
-        for (line_num, line_result) in reader.lines().enumerate() {
-            let line = line_result?;
-            lines.push(line.clone());
+    /// Indexes `lines` that did not come from a single file.
+    pub fn from_lines(lines: Vec<String>) -> Self {
+        Self::build(lines, |_, _| {})
+    }
 
-            let words = line
-                .split_whitespace()
-                .map(|word| {
-                    word.to_lowercase()
-                        .chars()
-                        .filter(|c| c.is_alphanumeric())
-                        .collect::<String>()
-                })
-                .filter(|word| !word.is_empty());
+    fn build(raw_lines: Vec<String>, mut on_progress: impl FnMut(usize, usize)) -> Self {
+        let total = raw_lines.len();
+
+        let mut lines = Vec::with_capacity(total);
+        let mut line_lengths = Vec::with_capacity(total);
+        let mut index: HashMap<String, Vec<Posting>> = HashMap::new();
+
+        for (line_num, line) in raw_lines.into_iter().enumerate() {
+            let mut length = 0;
+            for (position, word) in query::tokenize(&line).enumerate() {
+                index.entry(word).or_default().push(Posting {
+                    line: line_num,
+                    position,
+                });
+                length += 1;
+            }
+            lines.push(line);
+            line_lengths.push(length);
 
-            for word in words {
-                index.entry(word).or_default().push(line_num);
+            if (line_num + 1).is_multiple_of(PROGRESS_INTERVAL) || line_num + 1 == total {
+                on_progress(line_num + 1, total);
             }
         }
-        Ok(WordIndex { lines, index })
+        let average_line_length = if total == 0 {
+            0.0
+        } else {
+            line_lengths.iter().sum::<usize>() as f64 / total as f64
+        };
+        let mut terms: Vec<String> = index.keys().cloned().collect();
+        terms.sort_unstable();
+        WordIndex {
+            lines,
+            index,
+            terms,
+            line_lengths,
+            average_line_length,
+        }
     }
 
+    /// Lines matching `query`, in order. Invalid queries match nothing.
     pub fn search(&self, query: &str) -> Vec<usize> {
+        self.search_cancellable(query, &CancellationToken::default())
+            .unwrap_or_default()
+    }
+
+    /// Like [`search`](Self::search), but reports invalid queries, and gives up
+    /// once `token` is cancelled. The token is checked between query terms and
+    /// periodically while combining their line lists.
+    pub fn search_cancellable(&self, query: &str, token: &CancellationToken) -> Result<Vec<usize>, SearchError> {
+        self.search_with_progress(query, token, &SearchOptions::default(), |_, _| {})
+    }
+
+    /// Like [`search_cancellable`](Self::search_cancellable), with `options`,
+    /// calling `on_progress(occurrences_searched, total_occurrences)` after each
+    /// query term, counting the occurrences of the words the terms stand for.
+    pub fn search_with_progress(
+        &self,
+        query: &str,
+        token: &CancellationToken,
+        options: &SearchOptions,
+        on_progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<usize>, SearchError> {
         log::debug!("WordIndex::search called with query: '{}'", query);
-        let query_words: Vec<String> = query
-            .split_whitespace()
-            .map(|word| {
-                word.to_lowercase()
-                    .chars()
-                    .filter(|c| c.is_alphanumeric())
-                    .collect::<String>()
-            })
-            .filter(|word| !word.is_empty())
+        let parsed = query::parse(query)?;
+        let expansions = self.expand_terms(&parsed, options);
+        let mut sorted_results: Vec<usize> = self
+            .matching(&parsed, &expansions, token, on_progress)?
+            .into_iter()
             .collect();
+        sorted_results.sort_unstable();
+        log::debug!("Search successful, returning results: {:?}", sorted_results);
+        Ok(sorted_results)
+    }
 
-        log::trace!("Parsed query_words: {:?}", query_words);
+    /// Like [`search_with_progress`](Self::search_with_progress), but scores the
+    /// lines with `options.bm25` and returns them best match first.
+    pub fn search_ranked(
+        &self,
+        query: &str,
+        token: &CancellationToken,
+        options: &SearchOptions,
+        on_progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<ScoredLine>, SearchError> {
+        log::debug!("WordIndex::search_ranked called with query: '{}'", query);
+        let parsed = query::parse(query)?;
+        let expansions = self.expand_terms(&parsed, options);
+        let lines = self.matching(&parsed, &expansions, token, on_progress)?;
+
+        let mut scores: HashMap<usize, f64> = lines.iter().map(|&line| (line, 0.0)).collect();
+        for term in parsed.included_terms() {
+            if token.is_cancelled() {
+                return Err(SearchError::Cancelled);
+            }
+            let frequencies = self.term_frequencies(term, &expansions[term]);
+            let idf = Bm25::idf(self.lines.len(), frequencies.len());
+            for (line, tf) in frequencies {
+                if let Some(score) = scores.get_mut(&line) {
+                    *score += options
+                        .bm25
+                        .term_score(idf, tf, self.line_lengths[line], self.average_line_length);
+                }
+            }
+        }
+        let mut hits: Vec<ScoredLine> = scores
+            .into_iter()
+            .map(|(line, score)| ScoredLine { line, score })
+            .collect();
+        ranking::sort(&mut hits, Sort::Relevance);
+        log::debug!("Ranked search successful, returning results: {:?}", hits);
+        Ok(hits)
+    }
 
-        if query_words.is_empty() {
-            log::debug!("Empty query_words, returning empty results.");
-            return Vec::new();
+    /// The indexed words each term of `parsed` stands for, looked up once per
+    /// search.
+    fn expand_terms<'q>(&self, parsed: &'q Query, options: &SearchOptions) -> Expansions<'q, '_> {
+        let mut expansions = HashMap::new();
+        for term in parsed.terms() {
+            if !expansions.contains_key(term) {
+                expansions.insert(term, self.expansions(term, options));
+            }
         }
+        expansions
+    }
+
+    /// The lines matching `parsed`, whose terms stand for `expansions`,
+    /// reporting progress as described for
+    /// [`search_with_progress`](Self::search_with_progress).
+    fn matching(
+        &self,
+        parsed: &Query,
+        expansions: &Expansions,
+        token: &CancellationToken,
+        on_progress: impl FnMut(usize, usize),
+    ) -> Result<HashSet<usize>, SearchError> {
+        log::trace!("Parsed query: {:?}", parsed);
+        let total = parsed
+            .terms()
+            .into_iter()
+            .map(|term| self.occurrences(&expansions[term]))
+            .sum();
+        let mut search = Search {
+            wi: self,
+            token,
+            expansions,
+            processed: 0,
+            total,
+            on_progress,
+        };
+        search.lines(parsed).ok_or_else(|| {
+            log::debug!("Search for {:?} cancelled.", parsed);
+            SearchError::Cancelled
+        })
+    }
 
-        let mut result_line_nums: Option<HashSet<usize>> = None;
-
-        for word in query_words {
-            log::trace!("Processing word: '{}'", word);
-            if let Some(line_nums_for_word) = self.index.get(&word) {
-                log::trace!("Found line numbers for '{}': {:?}", word, line_nums_for_word);
-                let current_word_set: HashSet<usize> =
-                    line_nums_for_word.iter().cloned().collect();
-                if let Some(ref mut existing_set) = result_line_nums {
-                    existing_set.retain(|line_num| current_word_set.contains(line_num));
-                    log::trace!("Retained line numbers: {:?}", existing_set);
-                } else {
-                    result_line_nums = Some(current_word_set);
-                    log::trace!("Initialized result_line_nums with: {:?}", result_line_nums);
+    /// Indexed words starting with `prefix`, in order.
+    pub fn words_with_prefix(&self, prefix: &str) -> &[String] {
+        let start = self.terms.partition_point(|term| term.as_str() < prefix);
+        let len = self.terms[start..].partition_point(|term| term.starts_with(prefix));
+        &self.terms[start..start + len]
+    }
+
+    /// Indexed words matching `pattern`, at most `max_expansions` of them.
+    fn expand(&self, pattern: &str, max_expansions: usize) -> Vec<&String> {
+        let mut words: Vec<&String> = self
+            .words_with_prefix(query::pattern_prefix(pattern))
+            .iter()
+            .filter(|word| query::wildcard_match(pattern, word))
+            .take(max_expansions + 1)
+            .collect();
+        if words.len() > max_expansions {
+            log::debug!("'{}' matches more than {} words, searching only those.", pattern, max_expansions);
+            words.truncate(max_expansions);
+        }
+        words
+    }
+
+    /// Indexed words within `max_distance` edits of `word`, closest first, at
+    /// most `max_expansions` of them.
+    fn fuzzy_expand(&self, word: &str, max_distance: usize, max_expansions: usize) -> Vec<(&String, usize)> {
+        if max_distance == 0 {
+            return self.index.get_key_value(word).map(|(word, _)| (word, 0)).into_iter().collect();
+        }
+        let mut words: Vec<(&String, usize)> = self
+            .terms
+            .iter()
+            .filter_map(|term| query::edit_distance(word, term, max_distance).map(|distance| (term, distance)))
+            .collect();
+        words.sort_by_key(|&(_, distance)| distance);
+        if words.len() > max_expansions {
+            log::debug!("'{}~{}' matches {} words, searching only {}.", word, max_distance, words.len(), max_expansions);
+            words.truncate(max_expansions);
+        }
+        words
+    }
+
+    /// The indexed words `term` names or stands for, each with its edit
+    /// distance from the term. With `options.auto_fuzzy`, a word that is not
+    /// indexed stands for the words near it, as if it were fuzzy.
+    fn expansions(&self, term: &query::Term, options: &SearchOptions) -> Vec<(&String, usize)> {
+        match term {
+            query::Term::Word(word) => match self.index.get_key_value(word) {
+                Some((word, _)) => vec![(word, 0)],
+                None if options.auto_fuzzy => {
+                    self.fuzzy_expand(word, query::auto_distance(word), options.max_expansions)
                 }
-            } else {
-                log::debug!("Word '{}' not found in index, returning empty results.", word);
-                return Vec::new();
+                None => Vec::new(),
+            },
+            query::Term::Pattern(pattern) => self
+                .expand(pattern, options.max_expansions)
+                .into_iter()
+                .map(|word| (word, 0))
+                .collect(),
+            query::Term::Fuzzy { word, max_distance } => {
+                let max_distance = max_distance.unwrap_or_else(|| query::auto_distance(word));
+                self.fuzzy_expand(word, max_distance, options.max_expansions)
             }
+            query::Term::Phrase(words) => words
+                .iter()
+                .filter_map(|word| self.index.get_key_value(word))
+                .map(|(word, _)| (word, 0))
+                .collect(),
         }
+    }
 
-        if let Some(final_set) = result_line_nums {
-            let mut sorted_results: Vec<usize> = final_set.into_iter().collect();
-            sorted_results.sort_unstable();
-            log::debug!("Search successful, returning results: {:?}", sorted_results);
-            sorted_results
-        } else {
-            log::debug!("No results found after processing all words.");
-            Vec::new()
+    /// Total occurrences of `words`.
+    fn occurrences(&self, words: &[(&String, usize)]) -> usize {
+        words.iter().map(|&(word, _)| self.index[word].len()).sum()
+    }
+
+    /// Where `term`, standing for `words`, occurs: for a phrase, the postings
+    /// of its first word that are followed by the rest of its words, otherwise
+    /// the postings of every word it stands for. `None` if a word of a phrase
+    /// is not indexed.
+    fn term_postings(&self, term: &query::Term, words: &[(&String, usize)]) -> Option<Vec<Posting>> {
+        let query::Term::Phrase(phrase) = term else {
+            let mut postings: Vec<Posting> = words
+                .iter()
+                .flat_map(|&(word, _)| self.index[word].iter().copied())
+                .collect();
+            postings.sort_unstable_by_key(|posting| (posting.line, posting.position));
+            return Some(postings);
+        };
+        let (first, rest) = phrase.split_first()?;
+        let starts = self.index.get(first)?;
+        // Each later word of a phrase must follow the first at its offset
+        let followers = rest
+            .iter()
+            .map(|word| self.index.get(word).map(|postings| postings.iter().copied().collect::<HashSet<Posting>>()))
+            .collect::<Option<Vec<_>>>()?;
+        Some(
+            starts
+                .iter()
+                .filter(|start| {
+                    followers.iter().enumerate().all(|(offset, postings)| {
+                        postings.contains(&Posting {
+                            line: start.line,
+                            position: start.position + offset + 1,
+                        })
+                    })
+                })
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// The lines `term`, standing for `words`, matches, or `None` if one of
+    /// its words is not indexed.
+    fn term_lines(&self, term: &query::Term, words: &[(&String, usize)]) -> Option<HashSet<usize>> {
+        Some(
+            self.term_postings(term, words)?
+                .into_iter()
+                .map(|posting| posting.line)
+                .collect(),
+        )
+    }
+
+    /// How often `term` occurs on each line it occurs on, counting occurrences
+    /// of the `words` it stands for by their [`ranking::fuzzy_weight`].
+    fn term_frequencies(&self, term: &query::Term, words: &[(&String, usize)]) -> HashMap<usize, f64> {
+        let mut frequencies = HashMap::new();
+        if let query::Term::Phrase(_) = term {
+            for posting in self.term_postings(term, words).unwrap_or_default() {
+                *frequencies.entry(posting.line).or_default() += 1.0;
+            }
+            return frequencies;
+        }
+        for &(word, distance) in words {
+            for posting in &self.index[word] {
+                *frequencies.entry(posting.line).or_default() += ranking::fuzzy_weight(distance);
+            }
         }
+        frequencies
     }
 
     pub fn fetch(&self, line_number: usize) -> Option<String> {
@@ -161,56 +596,95 @@ impl WordIndex {
     }
 }
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Cli {
-    #[clap(short, long, value_delimiter = ',', help = "IP:PORT addresses to listen on (comma-separated)")]
-    addresses: Vec<String>,
-    #[clap(short, long, action = clap::ArgAction::Count, help = "Enable verbose logging. Use -vv for more verbose output.")]
-    verbose: u8,
+/// The indexed words each term of a query stands for, with their edit
+/// distances from the term.
+type Expansions<'q, 'i> = HashMap<&'q query::Term, Vec<(&'i String, usize)>>;
+
+/// One run of [`WordIndex::search_with_progress`], evaluating a parsed query.
+struct Search<'a, F> {
+    wi: &'a WordIndex,
+    token: &'a CancellationToken,
+    expansions: &'a Expansions<'a, 'a>,
+    processed: usize,
+    total: usize,
+    on_progress: F,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
-
-    // Initialize logger based on verbose level
-    match cli.verbose {
-        0 => std::env::set_var("RUST_LOG", "info"),
-        1 => std::env::set_var("RUST_LOG", "debug"),
-        _ => std::env::set_var("RUST_LOG", "trace"),
+impl<F: FnMut(usize, usize)> Search<'_, F> {
+    /// The lines matching `query`, or `None` once cancelled.
+    fn lines(&mut self, query: &Query) -> Option<HashSet<usize>> {
+        if self.token.is_cancelled() {
+            return None;
+        }
+        match query {
+            Query::Term(term) => {
+                log::trace!("Processing term: {:?}", term);
+                let words = &self.expansions[term];
+                let lines = self.wi.term_lines(term, words).unwrap_or_default();
+                log::trace!("Found line numbers for {:?}: {:?}", term, lines);
+                self.processed += self.wi.occurrences(words);
+                (self.on_progress)(self.processed, self.total);
+                Some(lines)
+            }
+            Query::Or(any) => {
+                let mut lines = HashSet::new();
+                for query in any {
+                    lines.extend(self.lines(query)?);
+                }
+                Some(lines)
+            }
+            Query::And { all, none } => {
+                let mut lines: Option<HashSet<usize>> = None;
+                for query in all {
+                    let matched = self.lines(query)?;
+                    match &mut lines {
+                        Some(lines) => self.retain(lines, |line| matched.contains(line))?,
+                        None => lines = Some(matched),
+                    }
+                    if lines.as_ref().is_some_and(HashSet::is_empty) {
+                        return lines;
+                    }
+                }
+                let mut lines = lines.unwrap_or_else(|| (0..self.wi.lines.len()).collect());
+                for query in none {
+                    let excluded = self.lines(query)?;
+                    self.retain(&mut lines, |line| !excluded.contains(line))?;
+                }
+                Some(lines)
+            }
+        }
     }
-    env_logger::init();
 
-    log::info!("Verbose level: {}", cli.verbose); // Replaced println with log::info
-
-    if cli.addresses.is_empty() {
-        log::error!("Error: No addresses provided. Please specify at least one address using --addresses ip:port."); // Replaced eprintln with log::error
-        std::process::exit(1);
+    /// Keeps the lines for which `keep` holds, checking for cancellation every
+    /// [`CANCEL_CHECK_INTERVAL`] lines.
+    fn retain(&self, lines: &mut HashSet<usize>, keep: impl Fn(&usize) -> bool) -> Option<()> {
+        let mut checked = 0usize;
+        let mut cancelled = false;
+        lines.retain(|line_num| {
+            checked += 1;
+            if checked.is_multiple_of(CANCEL_CHECK_INTERVAL) && self.token.is_cancelled() {
+                cancelled = true;
+            }
+            !cancelled && keep(line_num)
+        });
+        (!cancelled).then_some(())
     }
+}
 
-    log::info!("Loading database from db.txt..."); // Replaced println with log::info
-    let word_index = match WordIndex::new("db.txt") {
-        Ok(wi) => Arc::new(wi),
-        Err(e) => {
-            log::error!("Failed to load db.txt: {}", e); // Replaced eprintln with log::error
-            std::process::exit(1);
-        }
-    };
-    log::info!("Database loaded successfully."); // Replaced println with log::info
-
-    let mut handler = IoHandler::new();
+/// Builds the JSON-RPC handler shared by every transport.
+fn build_handler(index: SharedIndex, options: ServerOptions) -> Handler {
+    let mut handler = lifecycle::new_handler();
 
     // RPC "search" method
-    let wi_search = Arc::clone(&word_index);
-    handler.add_method("search", move |params: Params| {
-        let wi = Arc::clone(&wi_search);
+    let index_search = index.clone();
+    handler.add_method_with_meta("search", move |params: Params, session: Meta| {
+        let wi = index_search.load_for(&session);
         async move {
             log::debug!("RPC 'search' method called with params: {:?}", params);
             match params.parse::<(String,)>() {
                 Ok((query,)) => {
                     log::trace!("Parsed query for 'search': '{}'", query);
-                    let results = wi.search(&query);
+                    let results = wi.search_cancellable(&query, &cancellation::current())?;
                     log::trace!("Results for 'search' query '{}': {:?}", query, results);
                     Ok(Value::Array(
                         results.into_iter().map(|n| Value::Number(n.into())).collect(),
@@ -228,50 +702,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    // RPC "initialize" method
-    handler.add_method("initialize", |params: Params| async move {
-        log::debug!("RPC method 'initialize' called with params: {:?}", params);
-        match params.parse::<InitializeParams>() {
-            Ok(parsed_params) => {
-                log::info!("Successfully parsed initialize parameters: {:?}", parsed_params);
-                if let Some(client_info) = &parsed_params.client_info {
-                    log::info!(
-                        "Client name: {}, version: {:?}",
-                        client_info.name,
-                        client_info.version.as_deref().unwrap_or("N/A")
-                    );
-                }
-
-                let result = InitializeResult {
-                    capabilities: ServerCapabilities {
-                        tools: ToolCapabilities { list_changed: true },
-                        search: SearchCapabilities { enabled: true },
-                        fetch: FetchCapabilities { enabled: true },
-                    },
-                };
-                match serde_json::to_value(result) {
-                    Ok(val) => Ok(val),
-                    Err(e) => {
-                        log::error!("Failed to serialize InitializeResult: {}", e);
-                        Err(Error::internal_error())
-                    }
-                }
-            }
-            Err(e) => {
-                log::error!("Failed to parse initialize parameters: {}", e);
-                Err(Error {
-                    code: ErrorCode::InvalidParams,
-                    message: format!("Invalid parameters for initialize: {}", e),
-                    data: None,
-                })
-            }
-        }
+    // MCP "notifications/initialized": the client is ready for normal operation
+    let roots_policy = options.roots.clone();
+    handler.add_notification_with_meta(lifecycle::INITIALIZED_NOTIFICATION, move |_params: Params, session: Meta| {
+        session.mark_initialized();
+        roots::spawn_refresh(session, &roots_policy);
     });
 
     // RPC "fetch" method
-    let wi_fetch = Arc::clone(&word_index);
-    handler.add_method("fetch", move |params: Params| {
-        let wi = Arc::clone(&wi_fetch);
+    let index_fetch = index.clone();
+    handler.add_method_with_meta("fetch", move |params: Params, session: Meta| {
+        let wi = index_fetch.load_for(&session);
         async move {
             log::debug!("RPC 'fetch' method called with params: {:?}", params);
             match params.parse::<(usize,)>() {
@@ -305,14 +746,320 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    // MCP "tools/list" and "tools/call" methods wrapping search and fetch
+    tools::register(&mut handler, index.clone(), options.tools.clone());
+
+    // MCP "resources/*" methods exposing db lines as db://line/{n} resources
+    resources::register(&mut handler, index.clone());
+
+    // MCP "notifications/roots/list_changed" re-reading the client's roots
+    roots::register(&mut handler, options.roots.clone());
+
+    // MCP "ping" method
+    keepalive::register(&mut handler);
+
+    // MCP "notifications/cancelled" aborting in-flight requests
+    cancellation::register(&mut handler);
+
+    // MCP "logging/setLevel" method controlling which logs reach the session
+    logging::register(&mut handler, options.sessions.clone());
+
+    // Admin "admin/reindex" method reloading db.txt on demand
+    if let Some(admin) = options.admin.clone() {
+        admin::register(&mut handler, admin);
+    }
+
+    // MCP "prompts/list" and "prompts/get" methods filled in from search results
+    if !options.prompts.is_empty() {
+        prompts::register(&mut handler, index.clone(), options.prompts.clone());
+    }
+
+    // MCP "completion/complete" method suggesting query terms and line numbers
+    completion::register(&mut handler, index, options.prompts.clone());
+
+    // RPC "initialize" method
+    // Registered last so the advertised capabilities cover every other method
+    let capabilities = ServerCapabilities::implemented_by(&handler, &options);
+    handler.add_method_with_meta(lifecycle::INITIALIZE, move |params: Params, session: Meta| {
+        let capabilities = capabilities.clone();
+        let instructions = options.instructions.clone();
+        async move {
+            log::debug!("RPC method 'initialize' called with params: {:?}", params);
+            match params.parse::<InitializeParams>() {
+                Ok(parsed_params) => {
+                    log::info!("Successfully parsed initialize parameters: {:?}", parsed_params);
+                    log::debug!("Client capabilities: {}", parsed_params.capabilities);
+                    if let Some(client_info) = &parsed_params.client_info {
+                        log::info!(
+                            "Client name: {}, version: {:?}",
+                            client_info.name,
+                            client_info.version.as_deref().unwrap_or("N/A")
+                        );
+                    }
+
+                    let protocol_version = negotiate_protocol_version(parsed_params.protocol_version.as_deref())
+                        .inspect_err(|_| {
+                            log::warn!(
+                                "Rejecting client with unsupported protocol version {:?}",
+                                parsed_params.protocol_version
+                            )
+                        })?;
+                    log::info!("Negotiated protocol version: {}", protocol_version);
+                    session.initialize(protocol_version, parsed_params.capabilities)?;
+
+                    let result = InitializeResult {
+                        protocol_version: protocol_version.into(),
+                        capabilities,
+                        server_info: ServerInfo::from_cargo(),
+                        instructions,
+                    };
+                    match serde_json::to_value(result) {
+                        Ok(val) => Ok(val),
+                        Err(e) => {
+                            log::error!("Failed to serialize InitializeResult: {}", e);
+                            Err(Error::internal_error())
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to parse initialize parameters: {}", e);
+                    Err(Error {
+                        code: ErrorCode::InvalidParams,
+                        message: format!("Invalid parameters for initialize: {}", e),
+                        data: None,
+                    })
+                }
+            }
+        }
+    });
+
+    handler
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+    /// Plain JSON-RPC over HTTP POST on each of --addresses
+    Http,
+    /// MCP Streamable HTTP (sessions and SSE) on each of --addresses, at /mcp
+    StreamableHttp,
+    /// Newline-delimited JSON-RPC over stdin/stdout
+    Stdio,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ToolContract {
+    /// search returns hits with line numbers and text; fetch takes a line number
+    Mcp,
+    /// search returns {results: [{id, title, url}]}; fetch takes an id and returns {id, title, text, url, metadata}
+    DeepResearch,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(short, long, value_enum, default_value_t = Transport::Http, help = "Transport to serve MCP over")]
+    transport: Transport,
+    #[clap(short, long, value_delimiter = ',', help = "IP:PORT addresses to listen on (comma-separated)")]
+    addresses: Vec<String>,
+    #[clap(long, help = "Usage instructions to return to clients during initialize")]
+    instructions: Option<String>,
+    #[clap(long, help = "Also advertise the legacy non-standard search/fetch capabilities")]
+    compat_legacy_capabilities: bool,
+    #[clap(long, default_value_t = 2, help = "Seconds between checks of db.txt and --tools for changes (0 disables reloading)")]
+    db_poll_interval: u64,
+    #[clap(long, help = "JSON file of prompt templates to serve instead of the built-in one")]
+    prompts: Option<std::path::PathBuf>,
+    #[clap(long, help = "JSON file of saved search tools to serve, reloaded when it changes")]
+    tools: Option<std::path::PathBuf>,
+    #[clap(long, help = "JSON object of per-tool annotations (title, readOnlyHint, ...) overriding the defaults")]
+    tool_annotations: Option<std::path::PathBuf>,
+    #[clap(long, value_enum, default_value_t = ToolContract::Mcp, help = "Shape of the built-in search and fetch tools")]
+    tool_contract: ToolContract,
+    #[clap(long, help = "URL prefix for record URLs under --tool-contract deep-research (default db://line/)")]
+    connector_base_url: Option<String>,
+    #[clap(long, default_value_t = Bm25::default().k1, help = "BM25 term frequency saturation for ranking search hits")]
+    bm25_k1: f64,
+    #[clap(long, default_value_t = Bm25::default().b, help = "BM25 line length normalization for ranking search hits, from 0 to 1")]
+    bm25_b: f64,
+    #[clap(long, default_value_t = SearchOptions::default().max_expansions, help = "Most indexed words a wildcard or fuzzy search term may expand to")]
+    max_expansions: usize,
+    #[clap(long, help = "Match search words that are not indexed against the words nearest them, as if written word~")]
+    auto_fuzzy: bool,
+    #[clap(long, default_value_t = 0, help = "Seconds between server pings on stdio and SSE sessions; other sessions close once idle this long plus --ping-timeout (0 disables both)")]
+    ping_interval: u64,
+    #[clap(long, default_value_t = 10, help = "Seconds to wait for a ping response before closing the session")]
+    ping_timeout: u64,
+    #[clap(long, value_delimiter = ',', help = "Directories whose files clients may serve themselves by declaring them as roots (comma-separated); without it, roots are only honored on stdio")]
+    allowed_roots: Vec<std::path::PathBuf>,
+    #[clap(long, value_delimiter = ',', help = "Origins, such as https://example.com, that may send Streamable HTTP requests (comma-separated); without it, only localhost origins may")]
+    allowed_origins: Vec<String>,
+    #[clap(long, help = "Serve admin methods such as admin/reindex (unauthenticated; trusted clients only)")]
+    admin: bool,
+    #[clap(short, long, action = clap::ArgAction::Count, help = "Enable verbose logging. Use -vv for more verbose output.")]
+    verbose: u8,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    // Initialize logger based on verbose level
+    match cli.verbose {
+        0 => std::env::set_var("RUST_LOG", "info"),
+        1 => std::env::set_var("RUST_LOG", "debug"),
+        _ => std::env::set_var("RUST_LOG", "trace"),
+    }
+    // Logs always go to stderr so they never interleave with stdio protocol messages,
+    // and to the clients that asked for them via logging/setLevel
+    let sessions = SessionStore::default();
+    let mut stderr_logger = env_logger::Builder::from_default_env();
+    stderr_logger.target(env_logger::Target::Stderr);
+    logging::init(stderr_logger, sessions.clone())?;
+
+    log::info!("Verbose level: {}", cli.verbose); // Replaced println with log::info
+
+    if cli.transport != Transport::Stdio && cli.addresses.is_empty() {
+        log::error!("Error: No addresses provided. Please specify at least one address using --addresses ip:port."); // Replaced eprintln with log::error
+        std::process::exit(1);
+    }
+
+    log::info!("Loading database from db.txt..."); // Replaced println with log::info
+    let index = match WordIndex::new("db.txt") {
+        Ok(wi) => SharedIndex::new(wi),
+        Err(e) => {
+            log::error!("Failed to load db.txt: {}", e); // Replaced eprintln with log::error
+            std::process::exit(1);
+        }
+    };
+    log::info!("Database loaded successfully."); // Replaced println with log::info
+
+    let prompt_templates = match &cli.prompts {
+        Some(path) => match prompts::load_templates(path) {
+            Ok(templates) => templates,
+            Err(e) => {
+                log::error!("Failed to load prompts from {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => prompts::default_templates(),
+    };
+
+    let tool_annotations = match &cli.tool_annotations {
+        Some(path) => match tools::load_annotations(path) {
+            Ok(annotations) => annotations,
+            Err(e) => {
+                log::error!("Failed to load tool annotations from {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => HashMap::new(),
+    };
+    let bm25 = match Bm25::new(cli.bm25_k1, cli.bm25_b) {
+        Ok(bm25) => bm25,
+        Err(e) => {
+            log::error!("Invalid BM25 parameters: {}", e.message);
+            std::process::exit(1);
+        }
+    };
+    let search_options = SearchOptions {
+        bm25,
+        max_expansions: cli.max_expansions,
+        auto_fuzzy: cli.auto_fuzzy,
+    };
+    let mut tool_registry =
+        tools::ToolRegistry::with_annotations(tool_annotations).with_search_options(search_options);
+    if cli.tool_contract == ToolContract::DeepResearch {
+        tool_registry = tool_registry.with_connector(connector::Connector {
+            base_url: cli.connector_base_url.clone(),
+        });
+    }
+    let tools_watcher = cli.tools.as_ref().map(|path| ToolsWatcher::new(path, tool_registry.clone()));
+    if let Some(watcher) = &tools_watcher {
+        if let Err(e) = watcher.poll() {
+            log::error!("Failed to load saved searches: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let roots_policy = if !cli.allowed_roots.is_empty() {
+        match roots::RootsPolicy::within(&cli.allowed_roots) {
+            Ok(policy) => policy,
+            Err(e) => {
+                log::error!("Invalid --allowed-roots: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if cli.transport == Transport::Stdio {
+        roots::RootsPolicy::Any
+    } else {
+        roots::RootsPolicy::Ignore
+    };
+
+    let db = Arc::new(DbWatcher::new("db.txt", index.clone()));
+    let options = ServerOptions {
+        instructions: cli.instructions,
+        compat_legacy_capabilities: cli.compat_legacy_capabilities,
+        watch_db: cli.db_poll_interval > 0,
+        prompts: prompt_templates,
+        admin: cli.admin.then(|| admin::AdminContext {
+            db: Arc::clone(&db),
+            tools: tool_registry.clone(),
+            sessions: sessions.clone(),
+        }),
+        tools: tool_registry,
+        watch_tools: tools_watcher.is_some() && cli.db_poll_interval > 0,
+        roots: roots_policy,
+        sessions: sessions.clone(),
+    };
+    let handler = build_handler(index, options);
+
+    if cli.db_poll_interval > 0 {
+        tokio::spawn(db.run(Duration::from_secs(cli.db_poll_interval), sessions.clone()));
+        if let Some(watcher) = tools_watcher {
+            tokio::spawn(Arc::new(watcher).run(Duration::from_secs(cli.db_poll_interval), sessions.clone()));
+        }
+    }
+
+    if cli.ping_interval > 0 {
+        tokio::spawn(keepalive::run(
+            sessions.clone(),
+            Duration::from_secs(cli.ping_interval),
+            Duration::from_secs(cli.ping_timeout),
+        ));
+    }
+
+    if cli.transport == Transport::Stdio {
+        log::info!("Serving on stdio.");
+        stdio::serve(handler, sessions, tokio::io::stdin(), tokio::io::stdout()).await?;
+        log::info!("stdio session ended, shutting down.");
+        return Ok(());
+    }
+
     let mut server_handles = Vec::new();
+    let mut streamable_handles = Vec::new();
+    let streamable =
+        Arc::new(StreamableHttp::new(handler.clone(), sessions).with_allowed_origins(cli.allowed_origins.clone()));
 
     for addr_str in cli.addresses {
         log::info!("Attempting to start server on {}...", addr_str); // Replaced println with log::info
         match addr_str.parse::<std::net::SocketAddr>() {
+            Ok(socket_addr) if cli.transport == Transport::StreamableHttp => {
+                match streamable_http::start(&socket_addr, Arc::clone(&streamable)) {
+                    Ok(handle) => {
+                        log::info!("Server listening on http://{}{}", socket_addr, streamable_http::ENDPOINT_PATH);
+                        streamable_handles.push(handle);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to start server on {}: {:?}", socket_addr, e);
+                    }
+                }
+            }
             Ok(socket_addr) => {
-                let server = ServerBuilder::new(handler.clone()) // Clone handler for each server
+                // Plain HTTP has no connection to hang a session on, so every request gets a stateless one
+                let stateless_session = |_: &hyper::Request<hyper::Body>| Arc::new(Session::stateless());
+                let server = ServerBuilder::with_meta_extractor(handler.clone(), stateless_session) // Clone handler for each server
                     .cors(DomainsValidation::Disabled)
+                    .request_middleware(plain_http::PostHandler::new(handler.clone()))
                     .start_http(&socket_addr);
 
                 match server {
@@ -331,7 +1078,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    if server_handles.is_empty() {
+    if server_handles.is_empty() && streamable_handles.is_empty() {
         log::error!("No servers were started successfully."); // Replaced eprintln with log::error
         return Ok(());
     }
@@ -352,6 +1099,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use jsonrpc_http_server::jsonrpc_core::IoHandler;
     // use std::fs; // Removed unused import
     use std::io::Write;
     use tempfile::NamedTempFile;
@@ -397,12 +1145,12 @@ mod tests {
     #[test]
     fn test_word_index_new_empty_file() {
         let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
-        // writeln!(temp_file, "").expect("Failed to write to temp file"); // Write an empty line to avoid EOF error on read_line
+        // writeln!(temp_file).expect("Failed to write to temp file"); // Write an empty line to avoid EOF error on read_line
         // The original WordIndex::new reads lines and pushes them. An empty file will result in an empty lines vector.
         // If the file has one empty line, lines vector will have one empty string.
 
         // Test with a file that has one empty line
-        writeln!(temp_file, "").expect("Failed to write one empty line to temp file");
+        writeln!(temp_file).expect("Failed to write one empty line to temp file");
         let wi_one_empty_line = WordIndex::new(temp_file.path().to_str().unwrap())
             .expect("Failed to load file with one empty line");
         assert_eq!(wi_one_empty_line.lines.len(), 1, "Should have one line for a file with one empty line");
@@ -439,6 +1187,127 @@ mod tests {
         assert!(results.is_empty());
     }
 
+    #[test]
+    fn test_search_quoted_phrase() {
+        let wi = word_index_from_test_db();
+        assert_eq!(wi.search("after empty line"), vec![8]);
+        assert!(wi.search("\"after empty line\"").is_empty(), "line 8 has \"an\" in between");
+        assert_eq!(wi.search("\"empty line\""), vec![6, 8]);
+        assert!(wi.search("\"line empty\"").is_empty());
+        assert_eq!(wi.search("\"line after\" empty"), vec![8]);
+        assert_eq!(wi.search("\"repeated words\""), vec![9]);
+        assert_eq!(wi.search("\"Comma, period.\""), vec![4]);
+    }
+
+    #[test]
+    fn test_search_boolean_operators() {
+        let wi = word_index_from_test_db();
+        assert_eq!(wi.search("hello OR numbers"), vec![0, 5]);
+        assert_eq!(wi.search("line -empty"), vec![1, 2]);
+        assert_eq!(wi.search("empty NOT after"), vec![6]);
+        assert_eq!(wi.search("(hello OR test) line"), vec![1]);
+        assert_eq!(wi.search("+\"empty line\" +after"), vec![8]);
+        assert_eq!(wi.search("NOT line").len(), wi.lines.len() - 4);
+        assert!(wi.search("hello OR (").is_empty());
+    }
+
+    #[test]
+    fn test_search_wildcards() {
+        let wi = word_index_from_test_db();
+        assert_eq!(wi.words_with_prefix("test"), ["test", "testing"]);
+        assert_eq!(wi.search("test*"), vec![1, 2]);
+        assert_eq!(wi.search("te?t"), vec![1]);
+        assert_eq!(wi.search("*case"), vec![3]);
+        assert_eq!(wi.search("rep*ted words"), vec![9]);
+        assert_eq!(wi.search("line -emp*"), vec![1, 2]);
+        assert!(wi.search("xyz*").is_empty());
+    }
+
+    #[test]
+    fn test_search_caps_wildcard_expansions() {
+        let wi = word_index_from_test_db();
+        let options = SearchOptions {
+            max_expansions: 1,
+            ..SearchOptions::default()
+        };
+        // Only "test" is searched, not "testing"
+        let lines = wi.search_with_progress("test*", &CancellationToken::default(), &options, |_, _| {});
+        assert_eq!(lines, Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_search_progress_counts_word_occurrences() {
+        let wi = word_index_from_test_db();
+        let mut reports = Vec::new();
+        let lines = wi.search_with_progress(
+            "test line",
+            &CancellationToken::default(),
+            &SearchOptions::default(),
+            |done, total| reports.push((done, total)),
+        );
+        assert_eq!(lines, Ok(vec![1]));
+        let (tests, lines) = (wi.index["test"].len(), wi.index["line"].len());
+        assert_eq!(reports, vec![(tests, tests + lines), (tests + lines, tests + lines)]);
+    }
+
+    #[test]
+    fn test_search_fuzzy_words() {
+        let wi = word_index_from_test_db();
+        assert!(wi.search("uppercse").is_empty(), "plain words match exactly by default");
+        assert_eq!(wi.search("uppercse~"), vec![3]);
+        assert_eq!(wi.search("uppercse~1"), vec![3]);
+        assert!(wi.search("uppercse~0").is_empty());
+        assert_eq!(wi.search("hellp~"), vec![0]);
+        assert_eq!(wi.search("lin~1"), vec![1, 2, 6, 8]);
+        assert!(wi.search("\"empty lnie\"").is_empty(), "phrases match exactly");
+        assert!(wi.search("nonexistentword").is_empty());
+    }
+
+    #[test]
+    fn test_search_auto_fuzzy_words() {
+        let wi = word_index_from_test_db();
+        let options = SearchOptions {
+            auto_fuzzy: true,
+            ..SearchOptions::default()
+        };
+        let search = |query| wi.search_with_progress(query, &CancellationToken::default(), &options, |_, _| {});
+        assert_eq!(search("uppercse"), Ok(vec![3]));
+        assert_eq!(search("uppercse -hello"), Ok(vec![3]));
+        assert_eq!(search("\"empty lnie\""), Ok(vec![]), "phrases match exactly");
+        assert_eq!(search("nonexistentword"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_fuzzy_hits_score_below_exact_hits() {
+        let wi = WordIndex::from_lines(vec!["the cat sat".into(), "the car sat".into()]);
+        let hits = wi
+            .search_ranked("cat~1", &CancellationToken::default(), &SearchOptions::default(), |_, _| {})
+            .unwrap();
+        assert_eq!(hits.iter().map(|hit| hit.line).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn test_search_reports_invalid_queries() {
+        let wi = word_index_from_test_db();
+        let Err(SearchError::Invalid(error)) = wi.search_cancellable("(hello", &CancellationToken::default()) else {
+            panic!("an unclosed group should not parse");
+        };
+        assert_eq!(error.position, 0);
+        let depth = query::MAX_DEPTH;
+        assert_eq!(wi.search(&format!("{}hello{}", "(".repeat(depth), ")".repeat(depth))), vec![0]);
+        assert!(wi.search(&format!("{}hello{}", "(".repeat(depth + 1), ")".repeat(depth + 1))).is_empty());
+    }
+
+    #[test]
+    fn test_search_cancellable_stops_when_cancelled() {
+        let wi = word_index_from_test_db();
+        let token = CancellationToken::default();
+        assert_eq!(wi.search_cancellable("test line", &token), Ok(vec![1]));
+        token.cancel();
+        assert_eq!(wi.search_cancellable("test line", &token), Err(SearchError::Cancelled));
+    }
+
     #[test]
     fn test_search_word_not_exists() {
         let wi = word_index_from_test_db();
@@ -541,7 +1410,7 @@ mod tests {
             match params.parse::<InitializeParams>() {
                 Ok(parsed_params) => {
                     // println!("Successfully parsed initialize parameters: {:?}", parsed_params);
-                    if let Some(client_info) = &parsed_params.client_info {
+                    if let Some(_client_info) = &parsed_params.client_info {
                         // println!(
                         //     "Client name: {}, version: {:?}",
                         //     client_info.name,
@@ -549,11 +1418,15 @@ mod tests {
                         // );
                     }
                     let result = InitializeResult {
+                        protocol_version: SUPPORTED_PROTOCOL_VERSIONS[0].into(),
                         capabilities: ServerCapabilities {
-                            tools: ToolCapabilities { list_changed: true },
-                            search: SearchCapabilities { enabled: true },
-                            fetch: FetchCapabilities { enabled: true },
+                            tools: Some(ToolCapabilities { list_changed: true }),
+                            search: Some(SearchCapabilities { enabled: true }),
+                            fetch: Some(FetchCapabilities { enabled: true }),
+                            ..Default::default()
                         },
+                        server_info: ServerInfo::from_cargo(),
+                        instructions: None,
                     };
                     match serde_json::to_value(result) {
                         Ok(val) => Ok(val),
@@ -603,13 +1476,13 @@ mod tests {
 
         // Check for new capabilities
         let tools_cap = capabilities.get("tools").expect("Capabilities should have tools");
-        assert_eq!(tools_cap.get("listChanged").expect("Tools should have listChanged").as_bool().unwrap(), true);
+        assert!(tools_cap.get("listChanged").expect("Tools should have listChanged").as_bool().unwrap());
 
         let search_cap = capabilities.get("search").expect("Capabilities should have search");
-        assert_eq!(search_cap.get("enabled").expect("Search should have enabled").as_bool().unwrap(), true);
+        assert!(search_cap.get("enabled").expect("Search should have enabled").as_bool().unwrap());
 
         let fetch_cap = capabilities.get("fetch").expect("Capabilities should have fetch");
-        assert_eq!(fetch_cap.get("enabled").expect("Fetch should have enabled").as_bool().unwrap(), true);
+        assert!(fetch_cap.get("enabled").expect("Fetch should have enabled").as_bool().unwrap());
     }
 
     #[test]
@@ -620,11 +1493,15 @@ mod tests {
             match params.parse::<InitializeParams>() {
                 Ok(_parsed_params) => {
                     let result = InitializeResult {
+                        protocol_version: SUPPORTED_PROTOCOL_VERSIONS[0].into(),
                         capabilities: ServerCapabilities {
-                            tools: ToolCapabilities { list_changed: false },
-                            search: SearchCapabilities { enabled: true },
-                            fetch: FetchCapabilities { enabled: true },
+                            tools: Some(ToolCapabilities { list_changed: false }),
+                            search: Some(SearchCapabilities { enabled: true }),
+                            fetch: Some(FetchCapabilities { enabled: true }),
+                            ..Default::default()
                         },
+                        server_info: ServerInfo::from_cargo(),
+                        instructions: None,
                     };
                     match serde_json::to_value(result) {
                         Ok(val) => Ok(val),
@@ -662,4 +1539,134 @@ mod tests {
         assert_eq!(error["code"], ErrorCode::InvalidParams.code());
         assert!(error["message"].as_str().unwrap().contains("Invalid parameters"));
     }
+
+    fn initialize_with_version(protocol_version: &str) -> serde_json::Value {
+        let handler = build_handler(
+            SharedIndex::new(word_index_from_test_db()),
+            ServerOptions {
+                instructions: Some("Search before you fetch.".into()),
+                ..Default::default()
+            },
+        );
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {
+                "protocolVersion": protocol_version,
+                "capabilities": {},
+                "clientInfo": {"name": "test-client"}
+            },
+            "id": 1
+        });
+        let response = handler
+            .handle_request_sync(&request.to_string(), Arc::new(Session::new("test".into())))
+            .unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn test_lifecycle_enforced_by_handler() {
+        let handler = build_handler(SharedIndex::new(word_index_from_test_db()), ServerOptions::default());
+        let session = Arc::new(Session::new("test".into()));
+        let call = |request: serde_json::Value| -> Option<serde_json::Value> {
+            handler
+                .handle_request_sync(&request.to_string(), Arc::clone(&session))
+                .map(|response| serde_json::from_str(&response).unwrap())
+        };
+        let search = serde_json::json!({"jsonrpc": "2.0", "method": "search", "params": ["hello"], "id": 1});
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {"protocolVersion": "2025-06-18", "capabilities": {}},
+            "id": 2
+        });
+
+        let response = call(search.clone()).unwrap();
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidRequest.code());
+
+        assert!(call(initialize.clone()).unwrap()["result"].is_object());
+        let initialized = serde_json::json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        assert!(call(initialized).is_none());
+        assert_eq!(call(search).unwrap()["result"], serde_json::json!([0]));
+
+        let response = call(initialize).unwrap();
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidRequest.code());
+    }
+
+    #[test]
+    fn test_initialize_negotiates_supported_version() {
+        for version in SUPPORTED_PROTOCOL_VERSIONS {
+            let response = initialize_with_version(version);
+            assert_eq!(response["result"]["protocolVersion"], *version);
+        }
+        let result = &initialize_with_version("2025-03-26")["result"];
+        assert_eq!(result["serverInfo"]["name"], env!("CARGO_PKG_NAME"));
+        assert_eq!(result["serverInfo"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(result["instructions"], "Search before you fetch.");
+    }
+
+    #[test]
+    fn test_initialize_rejects_unsupported_version() {
+        let response = initialize_with_version("1999-01-01");
+        assert!(response["result"].is_null());
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidParams.code());
+        assert_eq!(response["error"]["message"], "Unsupported protocol version");
+        assert_eq!(response["error"]["data"]["requested"], "1999-01-01");
+        assert_eq!(
+            response["error"]["data"]["supported"],
+            serde_json::json!(SUPPORTED_PROTOCOL_VERSIONS)
+        );
+    }
+
+    fn initialize_capabilities(options: ServerOptions) -> serde_json::Value {
+        let handler = build_handler(SharedIndex::new(word_index_from_test_db()), options);
+        let request = r#"{"jsonrpc": "2.0", "method": "initialize", "params": {"capabilities": {}}, "id": 1}"#;
+        let response = handler
+            .handle_request_sync(request, Arc::new(Session::new("test".into())))
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        response["result"]["capabilities"].clone()
+    }
+
+    #[test]
+    fn test_capabilities_match_implemented_features() {
+        let capabilities = initialize_capabilities(ServerOptions::default());
+        assert_eq!(capabilities["tools"]["listChanged"], false);
+        assert_eq!(capabilities["resources"]["subscribe"], true);
+        assert_eq!(capabilities["resources"]["listChanged"], false);
+        assert_eq!(capabilities["logging"], serde_json::json!({}));
+        assert_eq!(capabilities["completions"], serde_json::json!({}));
+        for missing in ["search", "fetch", "prompts"] {
+            assert!(capabilities.get(missing).is_none(), "unexpected capability '{}'", missing);
+        }
+    }
+
+    #[test]
+    fn test_capabilities_legacy_shape() {
+        let capabilities = initialize_capabilities(ServerOptions {
+            compat_legacy_capabilities: true,
+            ..Default::default()
+        });
+        assert_eq!(capabilities["tools"]["listChanged"], true);
+        assert_eq!(capabilities["search"]["enabled"], true);
+        assert_eq!(capabilities["fetch"]["enabled"], true);
+    }
+
+    #[test]
+    fn test_capabilities_include_configured_prompts() {
+        let capabilities = initialize_capabilities(ServerOptions {
+            prompts: prompts::default_templates(),
+            ..Default::default()
+        });
+        assert_eq!(capabilities["prompts"]["listChanged"], false);
+    }
+
+    #[test]
+    fn test_capabilities_announce_tool_list_changes_when_tools_can_change() {
+        let capabilities = initialize_capabilities(ServerOptions {
+            watch_tools: true,
+            ..Default::default()
+        });
+        assert_eq!(capabilities["tools"]["listChanged"], true);
+    }
 }
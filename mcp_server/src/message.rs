@@ -0,0 +1,124 @@
+//! Sorting incoming JSON-RPC messages before they reach the handler.
+//!
+//! Clients send requests, notifications and, in answer to the server's own
+//! requests, responses, alone or in a batch. Responses are meant for the
+//! [`Session`](crate::session::Session) rather than the handler, and whether
+//! anything else needs an answer decides how each transport replies.
+
+use serde_json::{json, Value};
+
+/// An incoming message, with client responses split off from the rest.
+#[derive(Debug, Default, PartialEq)]
+pub struct Incoming {
+    /// Responses to requests the server sent the client.
+    pub responses: Vec<Value>,
+    /// What the handler should see, re-encoded if responses were removed from a
+    /// batch. `None` if the message was nothing but responses.
+    pub rest: Option<String>,
+    /// Whether `rest` holds any request, which will be answered.
+    pub has_requests: bool,
+    /// An error to answer with directly, for input the handler would ignore.
+    pub error: Option<String>,
+}
+
+/// Splits `body`, one message or a batch. Unparseable bodies go to the handler
+/// as they are, so that it answers them with the right error. An empty batch,
+/// which the handler would leave unanswered, gets an Invalid Request error.
+pub fn split(body: &str) -> Incoming {
+    let whole = || Incoming {
+        rest: Some(body.to_owned()),
+        ..Incoming::default()
+    };
+    match serde_json::from_str::<Value>(body) {
+        Ok(Value::Array(batch)) if batch.is_empty() => Incoming {
+            error: Some(json!({"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid request"}, "id": null}).to_string()),
+            ..Incoming::default()
+        },
+        Ok(Value::Array(batch)) => {
+            let rewrite = batch.iter().any(|message| !message.is_object() || is_response(message));
+            let (responses, rest): (Vec<Value>, Vec<Value>) = batch
+                .into_iter()
+                // The handler rejects a whole batch holding a non-object as unparseable, but
+                // answers an empty object with the Invalid Request each element should get
+                .map(|message| if message.is_object() { message } else { json!({}) })
+                .partition(is_response);
+            let has_requests = rest.iter().any(is_request);
+            let rest = match (rewrite, rest.is_empty()) {
+                (false, _) => Some(body.to_owned()),
+                (true, true) => None,
+                (true, false) => Some(Value::Array(rest).to_string()),
+            };
+            Incoming {
+                responses,
+                rest,
+                has_requests,
+                error: None,
+            }
+        }
+        Ok(message) if is_response(&message) => Incoming {
+            responses: vec![message],
+            ..Incoming::default()
+        },
+        Ok(message) => Incoming {
+            has_requests: is_request(&message),
+            ..whole()
+        },
+        Err(_) => whole(),
+    }
+}
+
+/// The messages in `message`, a single message or a batch.
+pub fn messages(message: &Value) -> Box<dyn Iterator<Item = &Value> + '_> {
+    match message {
+        Value::Array(batch) => Box::new(batch.iter()),
+        single => Box::new(std::iter::once(single)),
+    }
+}
+
+/// Whether `message` is a request, which must be answered.
+pub fn is_request(message: &Value) -> bool {
+    message.get("method").is_some() && message.get("id").is_some()
+}
+
+/// Whether `message` is a JSON-RPC response, as sent by clients answering our
+/// pings and other requests.
+pub fn is_response(message: &Value) -> bool {
+    message.get("method").is_none()
+        && message.get("id").is_some()
+        && (message.get("result").is_some() || message.get("error").is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_separates_client_responses() {
+        let response = json!({"jsonrpc": "2.0", "id": "ping-0", "result": {}});
+        let request = json!({"jsonrpc": "2.0", "method": "ping", "id": 1});
+        let notification = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+
+        let incoming = split(&json!([response, request, notification]).to_string());
+        assert_eq!(incoming.responses, vec![response.clone()]);
+        assert_eq!(incoming.rest, Some(json!([request, notification]).to_string()));
+        assert!(incoming.has_requests);
+
+        let incoming = split(&response.to_string());
+        assert_eq!(incoming.rest, None);
+        let incoming = split(&json!([notification]).to_string());
+        assert!(incoming.rest.is_some() && !incoming.has_requests);
+    }
+
+    #[test]
+    fn test_split_passes_invalid_input_through() {
+        for body in ["{not json", r#"[{"jsonrpc": "2.0"}]"#, r#"{"jsonrpc": "2.0"}"#] {
+            let incoming = split(body);
+            assert_eq!(incoming.rest.as_deref(), Some(body));
+            assert!(!incoming.has_requests);
+        }
+        assert_eq!(split("[1, \"two\"]").rest, Some("[{},{}]".into()));
+        let incoming = split("[]");
+        assert_eq!(incoming.rest, None);
+        assert!(incoming.error.is_some());
+    }
+}
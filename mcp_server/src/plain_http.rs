@@ -0,0 +1,137 @@
+//! POST handling for the plain JSON-RPC over HTTP transport.
+//!
+//! `jsonrpc-http-server` answers a POST holding only notifications with an empty
+//! `200 OK`, where MCP requires `202 Accepted`. [`PostHandler`] takes over JSON
+//! POSTs to answer them the MCP way, and leaves everything else, such as CORS
+//! preflights and wrong content types, to the server.
+
+use std::sync::Arc;
+
+use jsonrpc_http_server::hyper::body::HttpBody;
+use jsonrpc_http_server::hyper::header::{self, HeaderValue};
+use jsonrpc_http_server::hyper::{self, Body, Method, Request, Response, StatusCode};
+use jsonrpc_http_server::{RequestMiddleware, RequestMiddlewareAction};
+
+use crate::lifecycle::Handler;
+use crate::message;
+use crate::session::Session;
+
+/// Largest request body accepted, as `jsonrpc-http-server` defaults to. The
+/// Streamable HTTP transport uses the same limit.
+pub const MAX_BODY_SIZE: usize = 5 * 1024 * 1024;
+
+pub struct PostHandler {
+    handler: Handler,
+}
+
+impl PostHandler {
+    pub fn new(handler: Handler) -> Self {
+        PostHandler { handler }
+    }
+}
+
+impl RequestMiddleware for PostHandler {
+    fn on_request(&self, request: Request<Body>) -> RequestMiddlewareAction {
+        if request.method() != Method::POST || !is_json(&request) {
+            return RequestMiddlewareAction::Proceed {
+                should_continue_on_invalid_cors: false,
+                request,
+            };
+        }
+        let handler = self.handler.clone();
+        RequestMiddlewareAction::Respond {
+            should_validate_hosts: true,
+            response: Box::pin(async move { Ok(handle_post(&handler, request).await) }),
+        }
+    }
+}
+
+fn is_json(request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Answers a POSTed message or batch: `200 OK` with the responses, `202
+/// Accepted` when nothing in it called for one, or `400 Bad Request` with the
+/// errors for input that held no valid request.
+pub async fn handle_post(handler: &Handler, request: Request<Body>) -> Response<Body> {
+    let body = match read_body(request.into_body(), MAX_BODY_SIZE).await {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return empty_response(StatusCode::PAYLOAD_TOO_LARGE),
+        Err(e) => {
+            log::error!("Failed to read request body: {}", e);
+            return empty_response(StatusCode::BAD_REQUEST);
+        }
+    };
+    let Ok(body) = String::from_utf8(body) else {
+        return empty_response(StatusCode::BAD_REQUEST);
+    };
+    // Stateless sessions send no requests, so any responses answer nothing
+    let incoming = message::split(&body);
+    let output = match (incoming.error, incoming.rest) {
+        (Some(error), _) => Some(error),
+        (None, Some(body)) => handler.handle_request(&body, Arc::new(Session::stateless())).await,
+        (None, None) => None,
+    };
+    match output {
+        Some(output) => {
+            let mut response = Response::new(Body::from(format!("{}\n", output)));
+            if !incoming.has_requests {
+                // Notifications that could not be accepted, as Streamable HTTP reports them
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+            }
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json; charset=utf-8"));
+            response
+        }
+        None => empty_response(StatusCode::ACCEPTED),
+    }
+}
+
+/// Reads `body` if it holds at most `limit` bytes, or `None` as soon as it is
+/// known to hold more: from its declared length, or once that much has arrived.
+pub async fn read_body(mut body: Body, limit: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    if body.size_hint().lower() > limit as u64 {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_body_stops_at_the_limit() {
+        assert_eq!(read_body(Body::from("1234"), 4).await.unwrap(), Some(b"1234".to_vec()));
+        assert_eq!(read_body(Body::from("12345"), 4).await.unwrap(), None, "declared length over the limit");
+
+        // A body of unknown length is cut off once it passes the limit, without
+        // waiting for the rest of it
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            sender.send_data("123".into()).await.unwrap();
+            sender.send_data("45".into()).await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        assert_eq!(read_body(body, 4).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,106 @@
+//! Progress notifications for requests that carry `_meta.progressToken`.
+
+use jsonrpc_http_server::jsonrpc_core::{Id, Params, Value};
+use serde_json::json;
+
+use crate::cancellation;
+use crate::session::Meta;
+
+pub const PROGRESS_NOTIFICATION: &str = "notifications/progress";
+
+#[derive(Debug)]
+struct Target {
+    session: Meta,
+    token: Value,
+    request: Option<Id>,
+}
+
+/// Reports progress of one request to its client. Reports are dropped if the
+/// client did not ask for progress, and are only sent while progress increases,
+/// as the protocol requires.
+#[derive(Debug, Default)]
+pub struct Progress {
+    target: Option<Target>,
+    last: Option<usize>,
+}
+
+impl Progress {
+    /// Progress for the request with `params`, handled on `session`. Must be
+    /// called from the method's future, like [`cancellation::current`].
+    pub fn for_request(session: &Meta, params: &Params) -> Self {
+        let token = match params {
+            Params::Map(map) => map
+                .get("_meta")
+                .and_then(|meta| meta.get("progressToken"))
+                .filter(|token| token.is_string() || token.is_number())
+                .cloned(),
+            _ => None,
+        };
+        Progress {
+            target: token.map(|token| Target {
+                session: session.clone(),
+                token,
+                request: cancellation::current_id(),
+            }),
+            last: None,
+        }
+    }
+
+    pub fn report(&mut self, progress: usize, total: usize, message: String) {
+        let Some(target) = &self.target else {
+            return;
+        };
+        if self.last.is_some_and(|last| progress <= last) {
+            return;
+        }
+        self.last = Some(progress);
+        target.session.notify_request(
+            target.request.as_ref(),
+            PROGRESS_NOTIFICATION,
+            json!({
+                "progressToken": target.token,
+                "progress": progress,
+                "total": total,
+                "message": message,
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+    use serde_json::Map;
+    use std::sync::Arc;
+
+    fn params(meta: Value) -> Params {
+        let mut map = Map::new();
+        map.insert("_meta".into(), meta);
+        Params::Map(map)
+    }
+
+    #[test]
+    fn test_progress_sent_only_when_requested_and_increasing() {
+        let session = Arc::new(Session::new("progress".into()));
+        let mut events = session.attach_standalone();
+
+        Progress::for_request(&session, &Params::None).report(1, 2, "ignored".into());
+        assert!(events.try_recv().is_err());
+
+        let mut progress = Progress::for_request(&session, &params(json!({"progressToken": "abc"})));
+        progress.report(1, 2, "half".into());
+        progress.report(1, 2, "again".into());
+        progress.report(2, 2, "done".into());
+        let sent: Vec<Value> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| serde_json::from_str(&event.data).unwrap())
+            .collect();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0]["method"], PROGRESS_NOTIFICATION);
+        assert_eq!(
+            sent[0]["params"],
+            json!({"progressToken": "abc", "progress": 1, "total": 2, "message": "half"})
+        );
+        assert_eq!(sent[1]["params"]["progress"], 2);
+    }
+}
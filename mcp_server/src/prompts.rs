@@ -0,0 +1,337 @@
+//! MCP prompts built from search results: `prompts/list` and `prompts/get`.
+//!
+//! A prompt template is text with `{argument}` placeholders. Getting a prompt
+//! fills the placeholders in, runs the template's search argument through
+//! [`WordIndex::search_ranked`], and embeds the best matching lines as
+//! `db://line/{n}` resources after the text, so clients get the records without
+//! a second round trip.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Params};
+use serde::{Deserialize, Serialize};
+
+use crate::cancellation;
+use crate::lifecycle::Handler;
+use crate::ranking::ScoredLine;
+use crate::session::Meta;
+use crate::resources;
+use crate::tools::Content;
+use crate::watch::SharedIndex;
+use crate::{SearchOptions, WordIndex};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// A prompt as configured in the `--prompts` file.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Prompt text; `{name}` is replaced with the value of argument `name`.
+    pub template: String,
+    #[serde(default)]
+    pub arguments: Vec<PromptArgument>,
+    /// The argument whose value is used as the search query.
+    #[serde(default = "default_search_argument")]
+    pub search_argument: String,
+    /// Most matching lines to embed in the prompt.
+    #[serde(default = "default_max_records")]
+    pub max_records: usize,
+}
+
+fn default_search_argument() -> String {
+    "query".into()
+}
+
+fn default_max_records() -> usize {
+    10
+}
+
+/// The prompts served when no `--prompts` file is given.
+pub fn default_templates() -> Vec<PromptTemplate> {
+    vec![PromptTemplate {
+        name: "answer-from-records".into(),
+        title: Some("Answer from records".into()),
+        description: Some("Answer a question using the database records matching a query.".into()),
+        template: "Answer the following question using only the records below, which match '{query}'.\n\nQuestion: {question}".into(),
+        arguments: vec![
+            PromptArgument {
+                name: "query".into(),
+                description: Some("Words the relevant records must contain.".into()),
+                required: true,
+            },
+            PromptArgument {
+                name: "question".into(),
+                description: Some("The question to answer.".into()),
+                required: true,
+            },
+        ],
+        search_argument: default_search_argument(),
+        max_records: default_max_records(),
+    }]
+}
+
+/// Reads prompt templates from a JSON file holding an array of templates.
+pub fn load_templates(path: impl AsRef<Path>) -> std::io::Result<Vec<PromptTemplate>> {
+    let file = std::fs::File::open(path)?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Prompt<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    arguments: &'a [PromptArgument],
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListPromptsResult<'a> {
+    prompts: Vec<Prompt<'a>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GetPromptParams {
+    name: String,
+    #[serde(default)]
+    arguments: HashMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptMessage {
+    pub role: &'static str,
+    pub content: Content,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetPromptResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub messages: Vec<PromptMessage>,
+}
+
+fn invalid_params(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message,
+        data: None,
+    }
+}
+
+/// Fills `template` in with `arguments` and the records matching its search argument.
+pub fn get_prompt(
+    wi: &WordIndex,
+    template: &PromptTemplate,
+    arguments: &HashMap<String, String>,
+) -> Result<GetPromptResult, Error> {
+    if let Some(missing) = template
+        .arguments
+        .iter()
+        .find(|arg| arg.required && !arguments.contains_key(&arg.name))
+    {
+        return Err(invalid_params(format!(
+            "Missing required argument '{}' for prompt '{}'",
+            missing.name, template.name
+        )));
+    }
+    let query = arguments.get(&template.search_argument).ok_or_else(|| {
+        invalid_params(format!(
+            "Missing argument '{}' for prompt '{}'",
+            template.search_argument, template.name
+        ))
+    })?;
+
+    let text = fill(&template.template, arguments);
+    let mut messages = vec![PromptMessage {
+        role: "user",
+        content: Content::Text { text },
+    }];
+
+    let hits = wi.search_ranked(query, &cancellation::current(), &SearchOptions::default(), |_, _| {})?;
+    log::debug!("Prompt '{}' query '{}' matched lines {:?}", template.name, query, hits);
+    if hits.is_empty() {
+        messages.push(PromptMessage {
+            role: "user",
+            content: Content::Text {
+                text: format!("No records match '{}'.", query),
+            },
+        });
+    }
+    for ScoredLine { line, .. } in hits.into_iter().take(template.max_records) {
+        for resource in resources::read_resource(wi, &resources::DbResource::line_uri(line))? {
+            messages.push(PromptMessage {
+                role: "user",
+                content: Content::Resource { resource },
+            });
+        }
+    }
+
+    Ok(GetPromptResult {
+        description: template.description.clone(),
+        messages,
+    })
+}
+
+/// Replaces each `{name}` placeholder in `template` with argument `name`, in a
+/// single pass so that placeholders inside values are left as they are.
+/// Placeholders naming no argument are kept.
+fn fill(template: &str, arguments: &HashMap<String, String>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find(['{', '}']).filter(|&end| after[end..].starts_with('}')) {
+            Some(end) if arguments.contains_key(&after[..end]) => {
+                text.push_str(&arguments[&after[..end]]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Registers `prompts/list` and `prompts/get` serving `templates`.
+pub fn register(handler: &mut Handler, index: SharedIndex, templates: Vec<PromptTemplate>) {
+    let templates = Arc::new(templates);
+
+    let templates_list = Arc::clone(&templates);
+    handler.add_method("prompts/list", move |params: Params| {
+        let templates = Arc::clone(&templates_list);
+        async move {
+            log::debug!("RPC 'prompts/list' method called with params: {:?}", params);
+            let prompts = templates
+                .iter()
+                .map(|t| Prompt {
+                    name: &t.name,
+                    title: t.title.as_deref(),
+                    description: t.description.as_deref(),
+                    arguments: &t.arguments,
+                })
+                .collect();
+            serde_json::to_value(ListPromptsResult { prompts }).map_err(|e| {
+                log::error!("Failed to serialize ListPromptsResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+
+    handler.add_method_with_meta("prompts/get", move |params: Params, session: Meta| {
+        let templates = Arc::clone(&templates);
+        let wi = index.load_for(&session);
+        async move {
+            log::debug!("RPC 'prompts/get' method called with params: {:?}", params);
+            let params = params.parse::<GetPromptParams>()?;
+            let template = templates
+                .iter()
+                .find(|t| t.name == params.name)
+                .ok_or_else(|| invalid_params(format!("Unknown prompt: {}", params.name)))?;
+            let result = get_prompt(&wi, template, &params.arguments)?;
+            serde_json::to_value(result).map_err(|e| {
+                log::error!("Failed to serialize GetPromptResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn arguments(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_get_prompt_embeds_matching_records() {
+        let wi = WordIndex::new("test_db.txt").unwrap();
+        let template = &default_templates()[0];
+        let result = get_prompt(&wi, template, &arguments(&[("query", "line"), ("question", "Which lines?")])).unwrap();
+
+        let value = serde_json::to_value(&result).unwrap();
+        let messages = value["messages"].as_array().unwrap();
+        assert!(messages[0]["content"]["text"].as_str().unwrap().contains("match 'line'"));
+        assert!(messages[0]["content"]["text"].as_str().unwrap().ends_with("Question: Which lines?"));
+        let uris: Vec<&str> = messages[1..]
+            .iter()
+            .map(|m| m["content"]["resource"]["uri"].as_str().unwrap())
+            .collect();
+        // Best match first: twice "line", then the shortest line
+        assert_eq!(uris, vec!["db://line/8", "db://line/1", "db://line/2", "db://line/6"]);
+        assert_eq!(messages[1]["content"]["type"], "resource");
+        assert_eq!(messages[1]["content"]["resource"]["text"], "A line after an empty line.");
+
+        let template = PromptTemplate {
+            max_records: 1,
+            ..template.clone()
+        };
+        let result = get_prompt(&wi, &template, &arguments(&[("query", "line"), ("question", "Which lines?")])).unwrap();
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(value["messages"].as_array().unwrap().len(), 2, "only the most relevant record is kept");
+        assert_eq!(value["messages"][1]["content"]["resource"]["uri"], "db://line/8");
+    }
+
+    #[test]
+    fn test_fill_replaces_each_placeholder_once() {
+        let values = arguments(&[("query", "{question}"), ("question", "Why?")]);
+        assert_eq!(fill("{query} / {question}", &values), "{question} / Why?");
+        assert_eq!(fill("{{query}} {other} {", &values), "{{question}} {other} {");
+    }
+
+    #[test]
+    fn test_get_prompt_requires_arguments() {
+        let wi = WordIndex::new("test_db.txt").unwrap();
+        let template = &default_templates()[0];
+        let error = get_prompt(&wi, template, &arguments(&[("query", "line")])).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert!(error.message.contains("question"));
+    }
+
+    #[test]
+    fn test_load_templates_from_json() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"[{{"name": "summarize", "template": "Summarize records about {{topic}}.",
+                 "arguments": [{{"name": "topic", "required": true}}], "searchArgument": "topic", "maxRecords": 2}}]"#
+        )
+        .unwrap();
+        let templates = load_templates(file.path()).unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].search_argument, "topic");
+        assert_eq!(templates[0].max_records, 2);
+
+        let wi = WordIndex::new("test_db.txt").unwrap();
+        let result = get_prompt(&wi, &templates[0], &arguments(&[("topic", "line")])).unwrap();
+        assert_eq!(result.messages.len(), 3, "text plus at most two records");
+    }
+}
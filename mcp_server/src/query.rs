@@ -0,0 +1,629 @@
+//! Parsing of search queries for [`WordIndex`](crate::WordIndex).
+//!
+//! A query is a list of clauses that must all match a line. A clause is a word,
+//! a phrase in double quotes whose words must appear in that order with nothing
+//! between them, or a query in parentheses. On top of that:
+//!
+//! - `a OR b` matches lines matching either side. It binds looser than the
+//!   implicit AND, so `a b OR c` means `(a b) OR c`. `AND` may be written out.
+//! - `+a` requires `a` and `-a` or `NOT a` excludes it, for the whole group the
+//!   clause is in: `a OR b -c` means `(a OR b)` without the lines matching `c`.
+//!
+//! Groups nest at most [`MAX_DEPTH`] deep, which also bounds how deep the
+//! parsed query is.
+//!
+//! Operators are only recognized in upper case, so `or` and `not` are words.
+//! Words are normalized the way lines are indexed, so `"Empty, line"` and
+//! `empty line` name the same words.
+//!
+//! Outside quotes, a word holding `*` or `?` is a pattern: `*` stands for any
+//! number of letters and digits and `?` for exactly one, so `test*` matches
+//! `testing` and `te?t` matches `test`. A pattern matches the lines of every
+//! indexed word it matches.
+//!
+//! `word~1` and `word~2` also match indexed words up to one or two edits (a
+//! character inserted, removed or replaced) away, and `word~` as many as suit
+//! its length: none up to two characters, one up to five, two beyond. With
+//! `SearchOptions::auto_fuzzy`, a plain word that is not indexed at all is
+//! matched as if written `word~`, so typos still find something.
+
+use std::fmt;
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode};
+use serde_json::json;
+
+use crate::cancellation;
+
+/// A single term of a query.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Term {
+    Word(String),
+    /// Two or more words that must be adjacent and in order.
+    Phrase(Vec<String>),
+    /// A word with wildcards, holding at least one letter or digit.
+    Pattern(String),
+    /// A word and the edits allowed to match it; `None` for as many as suit its
+    /// length.
+    Fuzzy { word: String, max_distance: Option<usize> },
+}
+
+/// Most groups a query may nest inside one another.
+pub const MAX_DEPTH: usize = 32;
+
+/// Largest edit distance a fuzzy word may allow.
+pub const MAX_FUZZY_DISTANCE: usize = 2;
+
+/// The edit distance `word~` allows for `word`.
+pub fn auto_distance(word: &str) -> usize {
+    match word.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => MAX_FUZZY_DISTANCE,
+    }
+}
+
+/// The number of characters inserted, removed or replaced to turn `a` into `b`,
+/// if it is at most `max`.
+pub fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // Distances from the prefixes of `a` to the prefix of `b` seen so far
+    let mut previous: Vec<usize> = (0..=a.len()).collect();
+    for (j, &bc) in b.iter().enumerate() {
+        let mut current = vec![j + 1; a.len() + 1];
+        for (i, &ac) in a.iter().enumerate() {
+            let replace = previous[i] + usize::from(ac != bc);
+            current[i + 1] = replace.min(previous[i + 1] + 1).min(current[i] + 1);
+        }
+        if current.iter().all(|&distance| distance > max) {
+            return None;
+        }
+        previous = current;
+    }
+    Some(previous[a.len()]).filter(|&distance| distance <= max)
+}
+
+/// The part of `pattern` before its first wildcard, which every word it
+/// matches starts with.
+pub fn pattern_prefix(pattern: &str) -> &str {
+    &pattern[..pattern.find(['*', '?']).unwrap_or(pattern.len())]
+}
+
+/// Whether `word` matches `pattern`, where `*` stands for any number of
+/// characters and `?` for exactly one.
+pub fn wildcard_match(pattern: &str, word: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    let (mut p, mut w) = (0, 0);
+    // The last `*` seen, and where in `word` its match currently ends
+    let mut star: Option<(usize, usize)> = None;
+    while w < word.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, w));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == word[w] => {
+                p += 1;
+                w += 1;
+            }
+            _ => match star {
+                // Let the `*` swallow one more character and try again
+                Some((star_p, star_w)) => {
+                    star = Some((star_p, star_w + 1));
+                    p = star_p + 1;
+                    w = star_w + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A parsed query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    Term(Term),
+    /// Lines matching every query in `all` and none in `none`. With `all`
+    /// empty, every line not matching `none`.
+    And { all: Vec<Query>, none: Vec<Query> },
+    /// Lines matching any of the queries; with none, no lines at all.
+    Or(Vec<Query>),
+}
+
+impl Query {
+    /// Every term in the query, in order.
+    pub fn terms(&self) -> Vec<&Term> {
+        match self {
+            Query::Term(term) => vec![term],
+            Query::And { all, none } => all.iter().chain(none).flat_map(Query::terms).collect(),
+            Query::Or(any) => any.iter().flat_map(Query::terms).collect(),
+        }
+    }
+
+    /// The terms a matching line contains, at least possibly: every term that
+    /// is not excluded.
+    pub fn included_terms(&self) -> Vec<&Term> {
+        match self {
+            Query::Term(term) => vec![term],
+            Query::And { all, .. } => all.iter().flat_map(Query::included_terms).collect(),
+            Query::Or(any) => any.iter().flat_map(Query::included_terms).collect(),
+        }
+    }
+
+    fn all_of(mut all: Vec<Query>) -> Query {
+        if all.len() == 1 {
+            all.remove(0)
+        } else {
+            Query::And { all, none: Vec::new() }
+        }
+    }
+
+    fn any_of(mut any: Vec<Query>) -> Query {
+        if any.len() == 1 {
+            any.remove(0)
+        } else {
+            Query::Or(any)
+        }
+    }
+}
+
+/// Why a query could not be parsed, and where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// Character offset into the query, from zero.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid query: {} at position {}", self.message, self.position)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error {
+            code: ErrorCode::InvalidParams,
+            message: e.to_string(),
+            data: Some(json!({ "position": e.position })),
+        }
+    }
+}
+
+/// Why a search produced no results.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchError {
+    Invalid(ParseError),
+    Cancelled,
+}
+
+impl From<ParseError> for SearchError {
+    fn from(e: ParseError) -> Self {
+        SearchError::Invalid(e)
+    }
+}
+
+impl From<SearchError> for Error {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::Invalid(e) => e.into(),
+            SearchError::Cancelled => cancellation::cancelled_error(),
+        }
+    }
+}
+
+/// Lowercases `text` and splits it into words of letters and digits, dropping
+/// everything else. Words that are left empty do not count.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split_whitespace().map(normalize).filter(|word| !word.is_empty())
+}
+
+fn normalize(word: &str) -> String {
+    word.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+/// Normalizes a word holding wildcards, keeping them. `None` if nothing but
+/// wildcards would be left.
+fn normalize_pattern(word: &str) -> Option<String> {
+    let pattern: String = word
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '*' || *c == '?')
+        .collect();
+    pattern.chars().any(char::is_alphanumeric).then_some(pattern)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Plus,
+    Minus,
+    Term(Term),
+}
+
+fn describe(token: Option<&Token>) -> &'static str {
+    match token {
+        None => "end of query",
+        Some(Token::Open) => "'('",
+        Some(Token::Close) => "')'",
+        Some(Token::And) => "AND",
+        Some(Token::Or) => "OR",
+        Some(Token::Not) => "NOT",
+        Some(Token::Plus) => "'+'",
+        Some(Token::Minus) => "'-'",
+        Some(Token::Term(_)) => "a term",
+    }
+}
+
+/// Splits `query` into tokens and their character positions. Words with no
+/// letters or digits, and quotes with no words, are dropped. An unterminated
+/// quote runs to the end of the query.
+fn lex(query: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut at = 0;
+    while at < chars.len() {
+        let start = at;
+        let c = chars[at];
+        at += 1;
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push((Token::Open, start)),
+            ')' => tokens.push((Token::Close, start)),
+            '+' | '-' if chars.get(at).is_some_and(|next| !next.is_whitespace() && *next != ')') => {
+                tokens.push((if c == '+' { Token::Plus } else { Token::Minus }, start));
+            }
+            '"' => {
+                let end = chars[at..].iter().position(|&c| c == '"').map_or(chars.len(), |n| at + n);
+                let text: String = chars[at..end].iter().collect();
+                at = end + 1;
+                let mut words: Vec<String> = tokenize(&text).collect();
+                match words.len() {
+                    0 => {}
+                    1 => tokens.push((Token::Term(Term::Word(words.remove(0))), start)),
+                    _ => tokens.push((Token::Term(Term::Phrase(words)), start)),
+                }
+            }
+            _ => {
+                while at < chars.len() && !chars[at].is_whitespace() && !"()\"".contains(chars[at]) {
+                    at += 1;
+                }
+                let word: String = chars[start..at].iter().collect();
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    word if word.contains(['*', '?']) => match normalize_pattern(word) {
+                        Some(pattern) => Token::Term(Term::Pattern(pattern)),
+                        None => continue,
+                    },
+                    word if word.contains('~') => match fuzzy(word, start)? {
+                        Some(term) => Token::Term(term),
+                        None => continue,
+                    },
+                    word => match normalize(word) {
+                        word if word.is_empty() => continue,
+                        word => Token::Term(Term::Word(word)),
+                    },
+                };
+                tokens.push((token, start));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// The fuzzy term for a word holding `~`, found at `position`. Only a `~` at the
+/// end, or followed by the distance, makes the word fuzzy.
+fn fuzzy(word: &str, position: usize) -> Result<Option<Term>, ParseError> {
+    let (base, distance) = word.rsplit_once('~').expect("the word holds '~'");
+    let max_distance = match distance {
+        "" => None,
+        digits if digits.chars().all(|c| c.is_ascii_digit()) => match digits.parse() {
+            Ok(distance) if distance <= MAX_FUZZY_DISTANCE => Some(distance),
+            _ => {
+                return Err(ParseError {
+                    message: format!("edit distance must be at most {}", MAX_FUZZY_DISTANCE),
+                    position: position + base.chars().count() + 1,
+                })
+            }
+        },
+        _ => {
+            let word = normalize(word);
+            return Ok((!word.is_empty()).then_some(Term::Word(word)));
+        }
+    };
+    let word = normalize(base);
+    Ok((!word.is_empty()).then_some(Term::Fuzzy { word, max_distance }))
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    /// Character length of the query, the position of its end.
+    len: usize,
+    /// Groups open around the next token.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.len, |(_, position)| *position)
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            message,
+            position: self.position(),
+        }
+    }
+
+    /// Parses clauses up to the end of the query, or up to and including the
+    /// `)` closing a group opened at `open`.
+    fn group(&mut self, open: Option<usize>) -> Result<Query, ParseError> {
+        let mut alternatives: Vec<Vec<Query>> = vec![Vec::new()];
+        let mut required = Vec::new();
+        let mut excluded = Vec::new();
+        // The AND or OR still waiting for its right-hand side
+        let mut pending: Option<(&'static str, usize)> = None;
+        loop {
+            let current = alternatives.last_mut().expect("there is always a current alternative");
+            match self.peek() {
+                None => match open {
+                    Some(position) => {
+                        return Err(ParseError {
+                            message: "'(' is never closed".into(),
+                            position,
+                        })
+                    }
+                    None => break,
+                },
+                Some(Token::Close) => {
+                    if open.is_none() {
+                        return Err(self.error("unexpected ')'".into()));
+                    }
+                    if let Some((operator, _)) = pending {
+                        return Err(self.error(format!("expected a term after {} but found ')'", operator)));
+                    }
+                    self.next += 1;
+                    break;
+                }
+                Some(token @ (Token::And | Token::Or)) => {
+                    let operator = if *token == Token::And { "AND" } else { "OR" };
+                    if current.is_empty() || pending.is_some() {
+                        return Err(self.error(format!("expected a term before {}", operator)));
+                    }
+                    pending = Some((operator, self.position()));
+                    if operator == "OR" {
+                        alternatives.push(Vec::new());
+                    }
+                    self.next += 1;
+                }
+                Some(Token::Not | Token::Minus) => {
+                    self.next += 1;
+                    excluded.push(self.operand()?);
+                }
+                Some(Token::Plus) => {
+                    self.next += 1;
+                    required.push(self.operand()?);
+                }
+                Some(_) => {
+                    let operand = self.operand()?;
+                    alternatives.last_mut().expect("there is always a current alternative").push(operand);
+                    pending = None;
+                }
+            }
+        }
+        if let Some((operator, position)) = pending {
+            return Err(ParseError {
+                message: format!("expected a term after {}", operator),
+                position,
+            });
+        }
+
+        let alternatives: Vec<Query> = alternatives
+            .into_iter()
+            .filter(|all| !all.is_empty())
+            .map(Query::all_of)
+            .collect();
+        if alternatives.is_empty() && required.is_empty() && excluded.is_empty() {
+            return match open {
+                Some(position) => Err(ParseError {
+                    message: "empty parentheses".into(),
+                    position,
+                }),
+                None => Ok(Query::Or(Vec::new())),
+            };
+        }
+        let mut all = required;
+        if !alternatives.is_empty() {
+            all.insert(0, Query::any_of(alternatives));
+        }
+        if excluded.is_empty() {
+            Ok(Query::all_of(all))
+        } else {
+            Ok(Query::And { all, none: excluded })
+        }
+    }
+
+    /// Parses a term or parenthesized group.
+    fn operand(&mut self) -> Result<Query, ParseError> {
+        match self.peek() {
+            Some(Token::Term(term)) => {
+                let term = term.clone();
+                self.next += 1;
+                Ok(Query::Term(term))
+            }
+            Some(Token::Open) => {
+                let open = self.position();
+                if self.depth == MAX_DEPTH {
+                    return Err(ParseError {
+                        message: format!("groups nest more than {} deep", MAX_DEPTH),
+                        position: open,
+                    });
+                }
+                self.next += 1;
+                self.depth += 1;
+                let group = self.group(Some(open));
+                self.depth -= 1;
+                group
+            }
+            token => {
+                let found = describe(token);
+                Err(self.error(format!("expected a term but found {}", found)))
+            }
+        }
+    }
+}
+
+/// Parses `query`. A query with no words parses to one that matches nothing.
+pub fn parse(query: &str) -> Result<Query, ParseError> {
+    Parser {
+        tokens: lex(query)?,
+        next: 0,
+        len: query.chars().count(),
+        depth: 0,
+    }
+    .group(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(word: &str) -> Query {
+        Query::Term(Term::Word(word.into()))
+    }
+
+    fn phrase(words: &[&str]) -> Query {
+        Query::Term(Term::Phrase(words.iter().map(|w| w.to_string()).collect()))
+    }
+
+    fn and(all: Vec<Query>) -> Query {
+        Query::And { all, none: Vec::new() }
+    }
+
+    #[test]
+    fn test_parse_words_and_phrases() {
+        assert_eq!(
+            parse(r#"after "Empty, line" Numbers"#),
+            Ok(and(vec![word("after"), phrase(&["empty", "line"]), word("numbers")]))
+        );
+        assert_eq!(parse(r#""hello""#), Ok(word("hello")));
+        assert_eq!(parse(r#"a "" "b c"#), Ok(and(vec![word("a"), phrase(&["b", "c"])])));
+        assert_eq!(parse("  !! "), Ok(Query::Or(vec![])));
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        assert_eq!(
+            parse("a b OR c"),
+            Ok(Query::Or(vec![and(vec![word("a"), word("b")]), word("c")]))
+        );
+        assert_eq!(parse("a AND b"), Ok(and(vec![word("a"), word("b")])));
+        assert_eq!(
+            parse("(a OR b) -c +d"),
+            Ok(Query::And {
+                all: vec![Query::Or(vec![word("a"), word("b")]), word("d")],
+                none: vec![word("c")],
+            })
+        );
+        assert_eq!(
+            parse("NOT (a b)"),
+            Ok(Query::And {
+                all: vec![],
+                none: vec![and(vec![word("a"), word("b")])],
+            })
+        );
+        let query = parse("(a OR b) -c +d").unwrap();
+        assert_eq!(query.included_terms(), vec![&Term::Word("a".into()), &Term::Word("b".into()), &Term::Word("d".into())]);
+        assert_eq!(parse("e-mail or - not"), Ok(and(vec![word("email"), word("or"), word("not")])));
+    }
+
+    #[test]
+    fn test_parse_patterns() {
+        let pattern = |pattern: &str| Query::Term(Term::Pattern(pattern.into()));
+        assert_eq!(parse("Test* te?t"), Ok(and(vec![pattern("test*"), pattern("te?t")])));
+        assert_eq!(parse("e-mail* * ?"), Ok(pattern("email*")));
+        assert_eq!(parse(r#""test*""#), Ok(word("test")));
+        assert_eq!(pattern_prefix("te?t*"), "te");
+        assert_eq!(pattern_prefix("*ing"), "");
+    }
+
+    #[test]
+    fn test_parse_fuzzy_words() {
+        let fuzzy = |word: &str, max_distance| {
+            Query::Term(Term::Fuzzy {
+                word: word.into(),
+                max_distance,
+            })
+        };
+        assert_eq!(parse("Hello~1 wrld~"), Ok(and(vec![fuzzy("hello", Some(1)), fuzzy("wrld", None)])));
+        assert_eq!(parse("a~b ~2"), Ok(word("ab")));
+        assert_eq!(parse("ok hello~3").unwrap_err().position, 9);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("uppercse", "uppercase", 2), Some(1));
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("line", "line", 0), Some(0));
+        assert_eq!(edit_distance("a", "abcd", 2), None);
+        assert_eq!(edit_distance("tés", "tes", 1), Some(1));
+        assert_eq!(auto_distance("ab"), 0);
+        assert_eq!(auto_distance("hello"), 1);
+        assert_eq!(auto_distance("uppercse"), 2);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("test*", "test"));
+        assert!(wildcard_match("test*", "testing"));
+        assert!(!wildcard_match("test*", "tes"));
+        assert!(wildcard_match("te?t", "text"));
+        assert!(!wildcard_match("te?t", "tet"));
+        assert!(wildcard_match("*e*e*", "repeated"));
+        assert!(wildcard_match("r*d", "repeated"));
+        assert!(!wildcard_match("r*x", "repeated"));
+        assert!(wildcard_match("?é?", "tés"));
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        let error = |query: &str| parse(query).unwrap_err();
+        assert_eq!(error("a OR").position, 2);
+        assert_eq!(error("OR a").position, 0);
+        assert_eq!(error("a OR OR b").position, 5);
+        assert_eq!(error("(a b").position, 0);
+        assert_eq!(error("a b)").position, 3);
+        assert_eq!(error("a ()").position, 2);
+        assert_eq!(error("a NOT").position, 5);
+        assert_eq!(error("é -(").position, 3);
+        assert_eq!(error("a OR)").message, "unexpected ')'");
+    }
+
+    #[test]
+    fn test_parse_limits_nesting() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        let error = parse(&format!("b {}", nested(MAX_DEPTH + 1))).unwrap_err();
+        assert_eq!(error.position, 2 + MAX_DEPTH);
+        assert_eq!(error.message, "groups nest more than 32 deep");
+        assert!(parse(&nested(5000)).is_err(), "deep nesting is refused, not overflowing the stack");
+    }
+}
@@ -0,0 +1,134 @@
+//! BM25 relevance ranking of search hits.
+//!
+//! A line matching a query scores the sum, over the query's terms it contains,
+//! of `idf * tf * (k1 + 1) / (tf + k1 * (1 - b + b * len / avg_len))`, where
+//! `tf` is how often the term occurs in the line, `len` is the line's length in
+//! words and `avg_len` the average over all lines. Excluded terms do not count.
+//!
+//! Words a term only matches approximately count less towards `tf` the further
+//! they are from it, so a line scores lower for a typo'd word than it would
+//! for the word itself.
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode};
+use serde::Deserialize;
+
+/// BM25 parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bm25 {
+    /// How quickly repeating a term stops raising a line's score.
+    pub k1: f64,
+    /// How much long lines are penalized, from 0 (not at all) to 1.
+    pub b: f64,
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Bm25 { k1: 1.2, b: 0.75 }
+    }
+}
+
+fn invalid_params(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message,
+        data: None,
+    }
+}
+
+impl Bm25 {
+    /// Checks that `k1` is finite and not negative and `b` lies between 0 and 1.
+    pub fn new(k1: f64, b: f64) -> Result<Self, Error> {
+        if !k1.is_finite() || k1 < 0.0 {
+            return Err(invalid_params(format!("k1 must be a finite non-negative number, got {}", k1)));
+        }
+        if !(0.0..=1.0).contains(&b) {
+            return Err(invalid_params(format!("b must be between 0 and 1, got {}", b)));
+        }
+        Ok(Bm25 { k1, b })
+    }
+
+    /// Inverse document frequency of a term found on `matching` of `lines` lines.
+    pub fn idf(lines: usize, matching: usize) -> f64 {
+        let (lines, matching) = (lines as f64, matching as f64);
+        (1.0 + (lines - matching + 0.5) / (matching + 0.5)).ln()
+    }
+
+    /// Score contributed by a term with inverse document frequency `idf`,
+    /// occurring `tf` times in a line of `length` words.
+    pub fn term_score(&self, idf: f64, tf: f64, length: usize, average_length: f64) -> f64 {
+        let relative_length = if average_length > 0.0 {
+            length as f64 / average_length
+        } else {
+            1.0
+        };
+        idf * tf * (self.k1 + 1.0) / (tf + self.k1 * (1.0 - self.b + self.b * relative_length))
+    }
+}
+
+/// How much an occurrence of a word `distance` edits away from a query term
+/// counts towards the term's frequency: fully for the word itself, half for
+/// each edit.
+pub fn fuzzy_weight(distance: usize) -> f64 {
+    0.5f64.powi(distance as i32)
+}
+
+/// Order of search hits.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Sort {
+    /// Best match first; equal scores in line order.
+    #[default]
+    Relevance,
+    /// Line order, ignoring scores.
+    Line,
+}
+
+/// A line matching a query, and how well it matches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoredLine {
+    pub line: usize,
+    pub score: f64,
+}
+
+/// Sorts `hits` in `order`.
+pub fn sort(hits: &mut [ScoredLine], order: Sort) {
+    match order {
+        Sort::Relevance => hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.line.cmp(&b.line))),
+        Sort::Line => hits.sort_by_key(|hit| hit.line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rare_terms_and_short_lines_score_higher() {
+        let bm25 = Bm25::default();
+        assert!(Bm25::idf(100, 1) > Bm25::idf(100, 50));
+        let idf = Bm25::idf(100, 10);
+        assert!(bm25.term_score(idf, 1.0, 3, 6.0) > bm25.term_score(idf, 1.0, 12, 6.0));
+        assert!(bm25.term_score(idf, 2.0, 6, 6.0) > bm25.term_score(idf, 1.0, 6, 6.0));
+        // Without length normalization only the term frequency matters
+        let flat = Bm25::new(1.2, 0.0).unwrap();
+        assert_eq!(flat.term_score(idf, 1.0, 3, 6.0), flat.term_score(idf, 1.0, 12, 6.0));
+        assert!(Bm25::new(1.2, 1.5).is_err());
+        assert!(Bm25::new(-1.0, 0.5).is_err());
+        for (k1, b) in [(f64::INFINITY, 0.5), (f64::NAN, 0.5), (1.2, f64::NAN), (1.2, f64::NEG_INFINITY)] {
+            assert_eq!(Bm25::new(k1, b).unwrap_err().code, ErrorCode::InvalidParams);
+        }
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let mut hits = vec![
+            ScoredLine { line: 3, score: 0.5 },
+            ScoredLine { line: 1, score: 0.5 },
+            ScoredLine { line: 2, score: 2.0 },
+        ];
+        sort(&mut hits, Sort::Relevance);
+        assert_eq!(hits.iter().map(|hit| hit.line).collect::<Vec<_>>(), vec![2, 1, 3]);
+        sort(&mut hits, Sort::Line);
+        assert_eq!(hits.iter().map(|hit| hit.line).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,350 @@
+//! Database lines as read-only MCP resources: `resources/list`, `resources/read`,
+//! `resources/templates/list` and `resources/subscribe`/`unsubscribe`.
+//!
+//! Every line is addressable as `db://line/{n}`, and an inclusive run of lines as
+//! `db://range/{start}-{end}`, using the same zero-based numbers as `fetch`.
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Params, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::lifecycle::Handler;
+use crate::session::{Meta, SessionStore};
+use crate::watch::{IndexChange, SharedIndex};
+use crate::WordIndex;
+
+/// Number of resources returned per `resources/list` page.
+pub const PAGE_SIZE: usize = 50;
+
+/// MCP error code for a resource URI that does not resolve.
+pub const RESOURCE_NOT_FOUND: i64 = -32002;
+
+pub const UPDATED_NOTIFICATION: &str = "notifications/resources/updated";
+pub const LIST_CHANGED_NOTIFICATION: &str = "notifications/resources/list_changed";
+
+const MIME_TYPE: &str = "text/plain";
+const LINE_PREFIX: &str = "db://line/";
+const RANGE_PREFIX: &str = "db://range/";
+pub const LINE_TEMPLATE: &str = "db://line/{n}";
+pub const RANGE_TEMPLATE: &str = "db://range/{start}-{end}";
+const DESCRIPTION_PREVIEW_CHARS: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbResource {
+    Line(usize),
+    /// Inclusive on both ends.
+    Range(usize, usize),
+}
+
+impl DbResource {
+    pub fn parse(uri: &str) -> Option<Self> {
+        if let Some(n) = uri.strip_prefix(LINE_PREFIX) {
+            return n.parse().ok().map(DbResource::Line);
+        }
+        let (start, end) = uri.strip_prefix(RANGE_PREFIX)?.split_once('-')?;
+        match (start.parse(), end.parse()) {
+            (Ok(start), Ok(end)) if start <= end => Some(DbResource::Range(start, end)),
+            _ => None,
+        }
+    }
+
+    pub fn line_uri(line: usize) -> String {
+        format!("{}{}", LINE_PREFIX, line)
+    }
+
+    /// Line numbers covered by this resource.
+    pub fn lines(&self) -> std::ops::RangeInclusive<usize> {
+        match *self {
+            DbResource::Line(n) => n..=n,
+            DbResource::Range(start, end) => start..=end,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub mime_type: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListResourcesResult {
+    resources: Vec<Resource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ListResourcesParams {
+    cursor: Option<String>,
+}
+
+/// Parameters of `resources/read`, `resources/subscribe` and `resources/unsubscribe`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResourceUriParams {
+    uri: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextResourceContents {
+    pub uri: String,
+    pub mime_type: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReadResourceResult {
+    contents: Vec<TextResourceContents>,
+}
+
+fn line_resource(line: usize, text: &str) -> Resource {
+    let preview: String = text.chars().take(DESCRIPTION_PREVIEW_CHARS).collect();
+    Resource {
+        uri: DbResource::line_uri(line),
+        name: format!("Line {}", line),
+        description: (!preview.is_empty()).then_some(preview),
+        mime_type: MIME_TYPE.into(),
+    }
+}
+
+/// Lists one page of line resources. The cursor is the line to start from.
+fn list_resources(wi: &WordIndex, cursor: Option<&str>, page_size: usize) -> Result<ListResourcesResult, Error> {
+    let start = match cursor {
+        None => 0,
+        Some(cursor) => cursor
+            .parse::<usize>()
+            .ok()
+            .filter(|start| *start <= wi.lines.len())
+            .ok_or_else(|| Error {
+                code: ErrorCode::InvalidParams,
+                message: format!("Invalid cursor: {}", cursor),
+                data: None,
+            })?,
+    };
+    let end = (start + page_size).min(wi.lines.len());
+    let resources = (start..end).map(|n| line_resource(n, &wi.lines[n])).collect();
+    Ok(ListResourcesResult {
+        resources,
+        next_cursor: (end < wi.lines.len()).then(|| end.to_string()),
+    })
+}
+
+fn not_found(uri: &str) -> Error {
+    Error {
+        code: ErrorCode::ServerError(RESOURCE_NOT_FOUND),
+        message: "Resource not found".into(),
+        data: Some(json!({ "uri": uri })),
+    }
+}
+
+pub fn read_resource(wi: &WordIndex, uri: &str) -> Result<Vec<TextResourceContents>, Error> {
+    let resource = DbResource::parse(uri).ok_or_else(|| not_found(uri))?;
+    resource
+        .lines()
+        .map(|n| {
+            wi.fetch(n).map(|text| TextResourceContents {
+                uri: DbResource::line_uri(n),
+                mime_type: MIME_TYPE.into(),
+                text,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| not_found(uri))
+}
+
+/// Tells every operating session about a reload of the index: `list_changed` if
+/// lines were added or removed, and `updated` for each subscribed resource that
+/// covers a changed line.
+pub fn notify_changes(sessions: &SessionStore, change: &IndexChange) {
+    for session in sessions.all() {
+        // Sessions serving their own roots are unaffected by db.txt
+        if !session.is_operating() || session.index().is_some() {
+            continue;
+        }
+        if change.list_changed {
+            session.notify(LIST_CHANGED_NOTIFICATION, json!({}));
+        }
+        for uri in session.subscriptions() {
+            let affected = DbResource::parse(&uri).is_some_and(|resource| {
+                change.changed_lines.iter().any(|n| resource.lines().contains(n))
+            });
+            if affected {
+                log::debug!("Resource {} updated for session {}", uri, session.id);
+                session.notify(UPDATED_NOTIFICATION, json!({ "uri": uri }));
+            }
+        }
+    }
+}
+
+fn resource_templates() -> Value {
+    json!({
+        "resourceTemplates": [
+            {
+                "uriTemplate": LINE_TEMPLATE,
+                "name": "Database line",
+                "description": "A single line of the database by zero-based line number.",
+                "mimeType": MIME_TYPE
+            },
+            {
+                "uriTemplate": RANGE_TEMPLATE,
+                "name": "Database line range",
+                "description": "Consecutive lines of the database, from start to end inclusive.",
+                "mimeType": MIME_TYPE
+            }
+        ]
+    })
+}
+
+/// Registers the `resources/*` methods on `handler`.
+pub fn register(handler: &mut Handler, index: SharedIndex) {
+    let index_list = index.clone();
+    handler.add_method_with_meta("resources/list", move |params: Params, session: Meta| {
+        let wi = index_list.load_for(&session);
+        async move {
+            log::debug!("RPC 'resources/list' method called with params: {:?}", params);
+            let params = match params {
+                Params::None => ListResourcesParams::default(),
+                params => params.parse::<ListResourcesParams>()?,
+            };
+            let result = list_resources(&wi, params.cursor.as_deref(), PAGE_SIZE)?;
+            serde_json::to_value(result).map_err(|e| {
+                log::error!("Failed to serialize ListResourcesResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+
+    handler.add_method_with_meta("resources/read", move |params: Params, session: Meta| {
+        let wi = index.load_for(&session);
+        async move {
+            log::debug!("RPC 'resources/read' method called with params: {:?}", params);
+            let params = params.parse::<ResourceUriParams>()?;
+            let contents = read_resource(&wi, &params.uri).inspect_err(|_| {
+                log::warn!("Resource not found: {}", params.uri);
+            })?;
+            serde_json::to_value(ReadResourceResult { contents }).map_err(|e| {
+                log::error!("Failed to serialize ReadResourceResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+
+    handler.add_method("resources/templates/list", |params: Params| async move {
+        log::debug!("RPC 'resources/templates/list' method called with params: {:?}", params);
+        Ok(resource_templates())
+    });
+
+    handler.add_method_with_meta("resources/subscribe", |params: Params, session: Meta| async move {
+        log::debug!("RPC 'resources/subscribe' method called with params: {:?}", params);
+        let params = params.parse::<ResourceUriParams>()?;
+        if DbResource::parse(&params.uri).is_none() {
+            return Err(not_found(&params.uri));
+        }
+        session.subscribe(&params.uri);
+        Ok(json!({}))
+    });
+
+    handler.add_method_with_meta("resources/unsubscribe", |params: Params, session: Meta| async move {
+        log::debug!("RPC 'resources/unsubscribe' method called with params: {:?}", params);
+        let params = params.parse::<ResourceUriParams>()?;
+        session.unsubscribe(&params.uri);
+        Ok(json!({}))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+    use std::sync::Arc;
+
+    fn word_index_from_test_db() -> WordIndex {
+        WordIndex::new("test_db.txt").expect("Failed to load test_db.txt")
+    }
+
+    #[test]
+    fn test_parse_uris() {
+        assert_eq!(DbResource::parse("db://line/3"), Some(DbResource::Line(3)));
+        assert_eq!(DbResource::parse("db://range/2-4"), Some(DbResource::Range(2, 4)));
+        assert_eq!(DbResource::parse("db://range/4-2"), None);
+        assert_eq!(DbResource::parse("db://line/x"), None);
+        assert_eq!(DbResource::parse("file:///etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_list_resources_paginates() {
+        let wi = word_index_from_test_db();
+        let first = list_resources(&wi, None, 4).unwrap();
+        assert_eq!(first.resources.len(), 4);
+        assert_eq!(first.resources[0].uri, "db://line/0");
+        assert_eq!(first.resources[0].description.as_deref(), Some("Hello world!"));
+        assert_eq!(first.next_cursor.as_deref(), Some("4"));
+
+        let last = list_resources(&wi, Some("8"), 4).unwrap();
+        assert_eq!(last.resources.len(), 2);
+        assert!(last.next_cursor.is_none());
+
+        assert!(list_resources(&wi, Some("bogus"), 4).is_err());
+    }
+
+    #[test]
+    fn test_read_line_and_range() {
+        let wi = word_index_from_test_db();
+        let line = read_resource(&wi, "db://line/1").unwrap();
+        assert_eq!(line.len(), 1);
+        assert_eq!(line[0].text, "This is a test line.");
+
+        let range = read_resource(&wi, "db://range/7-8").unwrap();
+        let texts: Vec<&str> = range.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["", "A line after an empty line."]);
+        assert_eq!(range[1].uri, "db://line/8");
+
+        let error = read_resource(&wi, "db://range/8-100").unwrap_err();
+        assert_eq!(error.code, ErrorCode::ServerError(RESOURCE_NOT_FOUND));
+        assert_eq!(error.data, Some(json!({"uri": "db://range/8-100"})));
+    }
+
+    #[test]
+    fn test_notify_changes_reaches_subscribers() {
+        let sessions = SessionStore::default();
+        let session = Arc::new(Session::new("s".into()));
+        session.initialize("2025-06-18", Value::Null).unwrap();
+        session.mark_initialized();
+        session.subscribe("db://line/2");
+        session.subscribe("db://range/4-6");
+        sessions.register(Arc::clone(&session));
+        let mut rx = session.attach_standalone();
+
+        notify_changes(
+            &sessions,
+            &IndexChange {
+                changed_lines: vec![5],
+                list_changed: false,
+            },
+        );
+        let event: Value = serde_json::from_str(&rx.try_recv().unwrap().data).unwrap();
+        assert_eq!(event["method"], UPDATED_NOTIFICATION);
+        assert_eq!(event["params"]["uri"], "db://range/4-6");
+        assert!(rx.try_recv().is_err(), "db://line/2 did not change");
+
+        notify_changes(
+            &sessions,
+            &IndexChange {
+                changed_lines: vec![10],
+                list_changed: true,
+            },
+        );
+        let event: Value = serde_json::from_str(&rx.try_recv().unwrap().data).unwrap();
+        assert_eq!(event["method"], LIST_CHANGED_NOTIFICATION);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! Client roots: which databases a session serves.
+//!
+//! When a client declares the `roots` capability, the server asks for its roots
+//! with `roots/list` once the session is operating, and again on every
+//! `notifications/roots/list_changed`. The `.txt` files under `file://` roots are
+//! indexed together, in path order, and served to that session in place of
+//! `db.txt`. A session whose roots hold no text files keeps serving `db.txt`.
+//!
+//! Roots name paths on the client's host, which are only the server's own when
+//! both run on the same host. A [`RootsPolicy`] decides which roots are honored:
+//! any on stdio, where the client started the server, and elsewhere only those
+//! under directories the server was told to share.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use jsonrpc_http_server::jsonrpc_core::Params;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::lifecycle::Handler;
+use crate::resources;
+use crate::session::Meta;
+use crate::WordIndex;
+
+pub const LIST_METHOD: &str = "roots/list";
+pub const LIST_CHANGED_NOTIFICATION: &str = "notifications/roots/list_changed";
+
+/// How long to wait for the client to answer `roots/list`.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most files indexed for one session, so a root at `/` cannot exhaust memory.
+const MAX_FILES: usize = 1000;
+
+/// Most bytes of text read for one session, over all of its files.
+const MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Which client roots the server honors.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RootsPolicy {
+    /// Roots are not asked for, and every session serves `db.txt`.
+    #[default]
+    Ignore,
+    /// Every root is honored.
+    Any,
+    /// Roots are honored inside these canonical directories only.
+    Within(Vec<PathBuf>),
+}
+
+impl RootsPolicy {
+    /// Honors roots inside `dirs`, which must exist.
+    pub fn within(dirs: &[PathBuf]) -> std::io::Result<Self> {
+        let dirs = dirs.iter().map(|dir| dir.canonicalize()).collect::<Result<_, _>>()?;
+        Ok(RootsPolicy::Within(dirs))
+    }
+
+    /// The paths among `roots` that may be served.
+    fn allowed(&self, roots: Vec<PathBuf>) -> Vec<PathBuf> {
+        match self {
+            RootsPolicy::Ignore => Vec::new(),
+            RootsPolicy::Any => roots,
+            RootsPolicy::Within(dirs) => roots
+                .into_iter()
+                .filter_map(|root| match root.canonicalize() {
+                    Ok(path) if dirs.iter().any(|dir| path.starts_with(dir)) => Some(path),
+                    _ => {
+                        log::warn!("Root {} is outside the allowed roots, skipping it.", root.display());
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Root {
+    uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ListRootsResult {
+    roots: Vec<Root>,
+}
+
+/// The local path of a `file://` root URI.
+pub fn root_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    if !path.starts_with('/') {
+        return None;
+    }
+    percent_decode(path).map(PathBuf::from)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The text files under `roots`, sorted and without duplicates. Symbolic links
+/// and hidden entries are skipped, so nothing outside the roots is reached.
+pub fn text_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for root in roots {
+        collect_text_files(root, &mut files);
+    }
+    files.sort();
+    files.dedup();
+    if files.len() > MAX_FILES {
+        log::warn!("Roots hold {} text files, indexing only the first {}.", files.len(), MAX_FILES);
+        files.truncate(MAX_FILES);
+    }
+    files
+}
+
+fn collect_text_files(path: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(metadata) = path.symlink_metadata() else {
+        log::warn!("Root {} is not accessible, skipping it.", path.display());
+        return;
+    };
+    if metadata.is_file() {
+        if path.extension().is_some_and(|ext| ext == "txt") {
+            files.push(path.to_path_buf());
+        }
+        return;
+    }
+    if !metadata.is_dir() || files.len() > MAX_FILES {
+        return;
+    }
+    let Ok(entries) = path.read_dir() else {
+        log::warn!("Cannot list {}, skipping it.", path.display());
+        return;
+    };
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().starts_with('.') {
+            collect_text_files(&entry.path(), files);
+        }
+    }
+}
+
+/// Indexes the lines of `files`, one after another. Files that cannot be read
+/// as UTF-8 text are skipped, as are those that would take the text read past
+/// [`MAX_BYTES`].
+pub fn index_files(files: &[PathBuf]) -> WordIndex {
+    let mut lines = Vec::new();
+    let mut budget = MAX_BYTES;
+    for file in files {
+        match read_text(file, budget) {
+            Ok(Some(text)) => {
+                budget -= text.len() as u64;
+                lines.extend(text.lines().map(String::from));
+            }
+            Ok(None) => log::warn!("Skipping {}: more than {} bytes of text left to read.", file.display(), budget),
+            Err(e) => log::warn!("Skipping {}: {}", file.display(), e),
+        }
+    }
+    WordIndex::from_lines(lines)
+}
+
+/// The text of `file`, or `None` if it is longer than `limit` bytes. No more
+/// than that is read.
+fn read_text(file: &Path, limit: u64) -> std::io::Result<Option<String>> {
+    let mut text = String::new();
+    std::fs::File::open(file)?.take(limit + 1).read_to_string(&mut text)?;
+    Ok((text.len() as u64 <= limit).then_some(text))
+}
+
+/// Asks the session's client for its roots and switches the session to the
+/// databases found under those `policy` allows.
+pub async fn refresh(session: Meta, policy: RootsPolicy) {
+    let response = match tokio::time::timeout(RESPONSE_TIMEOUT, session.request(LIST_METHOD, json!({}))).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(_)) => return,
+        Err(_) => {
+            log::warn!("Session {} did not answer {} within {:?}.", session.id, LIST_METHOD, RESPONSE_TIMEOUT);
+            return;
+        }
+    };
+    let roots = match response.get("result").cloned().map(serde_json::from_value::<ListRootsResult>) {
+        Some(Ok(result)) => result.roots,
+        Some(Err(e)) => {
+            log::warn!("Session {} sent an invalid {} result: {}", session.id, LIST_METHOD, e);
+            return;
+        }
+        None => {
+            log::warn!("Session {} failed {}: {}", session.id, LIST_METHOD, response["error"]);
+            return;
+        }
+    };
+    log::debug!("Session {} roots: {:?}", session.id, roots);
+
+    let paths: Vec<PathBuf> = roots.iter().filter_map(|root| root_path(&root.uri)).collect();
+    // Walking and reading the roots is blocking file system work
+    let loaded = tokio::task::spawn_blocking(move || {
+        let files = text_files(&policy.allowed(paths));
+        (!files.is_empty()).then(|| (files.len(), index_files(&files)))
+    })
+    .await;
+    let index = match loaded {
+        Ok(Some((files, index))) => {
+            log::info!(
+                "Session {} serving {} lines from {} files under its roots.",
+                session.id,
+                index.lines.len(),
+                files
+            );
+            Some(index)
+        }
+        Ok(None) => {
+            log::info!("Session {} roots hold no text files, serving db.txt.", session.id);
+            None
+        }
+        Err(e) => {
+            log::error!("Indexing the roots of session {} failed: {}", session.id, e);
+            return;
+        }
+    };
+    let serves_roots = index.is_some();
+    let served_roots = session.set_index(index).is_some();
+    if served_roots || serves_roots {
+        session.notify(resources::LIST_CHANGED_NOTIFICATION, json!({}));
+    }
+}
+
+/// Runs [`refresh`] on its own task, if the session's client supports roots
+/// and `policy` honors any.
+pub fn spawn_refresh(session: Meta, policy: &RootsPolicy) {
+    if *policy == RootsPolicy::Ignore || !session.client_supports("roots") {
+        return;
+    }
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(refresh(session, policy.clone()));
+        }
+        Err(_) => log::warn!("No runtime to ask session {} for its roots.", session.id),
+    }
+}
+
+/// Registers the `notifications/roots/list_changed` handler on `handler`,
+/// honoring roots as `policy` allows.
+pub fn register(handler: &mut Handler, policy: RootsPolicy) {
+    handler.add_notification_with_meta(LIST_CHANGED_NOTIFICATION, move |params: Params, session: Meta| {
+        log::debug!("RPC '{}' notification called with params: {:?}", LIST_CHANGED_NOTIFICATION, params);
+        spawn_refresh(session, &policy);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_root_paths() {
+        assert_eq!(root_path("file:///home/me/notes"), Some(PathBuf::from("/home/me/notes")));
+        assert_eq!(root_path("file://localhost/a%20b"), Some(PathBuf::from("/a b")));
+        assert_eq!(root_path("https://example.com/"), None);
+        assert_eq!(root_path("file:///bad%zz"), None);
+    }
+
+    #[test]
+    fn test_text_files_stay_under_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b.txt"), "second file\n").unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/a.txt"), "nested\n").unwrap();
+        std::fs::write(dir.path().join("image.png"), "not text").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD.txt"), "hidden").unwrap();
+
+        let files = text_files(&[dir.path().to_path_buf(), dir.path().join("b.txt")]);
+        assert_eq!(files, vec![dir.path().join("b.txt"), dir.path().join("sub/a.txt")]);
+        let index = index_files(&files);
+        assert_eq!(index.lines, vec!["second file", "nested"]);
+        assert_eq!(index.search("nested"), vec![1]);
+    }
+
+    #[test]
+    fn test_policy_limits_roots() {
+        let shared = tempfile::tempdir().unwrap();
+        let private = tempfile::tempdir().unwrap();
+        std::fs::create_dir(shared.path().join("sub")).unwrap();
+        let roots = vec![shared.path().join("sub"), private.path().to_path_buf(), shared.path().join("missing")];
+
+        let policy = RootsPolicy::within(&[shared.path().to_path_buf()]).unwrap();
+        assert_eq!(policy.allowed(roots.clone()), vec![shared.path().join("sub").canonicalize().unwrap()]);
+        assert_eq!(RootsPolicy::Any.allowed(roots.clone()), roots);
+        assert!(RootsPolicy::Ignore.allowed(roots).is_empty());
+    }
+
+    #[test]
+    fn test_reads_are_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("big.txt");
+        std::fs::write(&file, "0123456789").unwrap();
+        assert_eq!(read_text(&file, 10).unwrap().as_deref(), Some("0123456789"));
+        assert_eq!(read_text(&file, 9).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_serves_roots_and_announces_it() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "from the client root\n").unwrap();
+        let session = Arc::new(Session::new("rooted".into()));
+        session.initialize("2025-06-18", json!({"roots": {"listChanged": true}})).unwrap();
+        session.mark_initialized();
+        let mut events = session.attach_standalone();
+
+        let refreshing = tokio::spawn(refresh(Arc::clone(&session), RootsPolicy::Any));
+        let request: Value = serde_json::from_str(&events.recv().await.unwrap().data).unwrap();
+        assert_eq!(request["method"], LIST_METHOD);
+        let uri = format!("file://{}", dir.path().display());
+        session.handle_response(&json!({"jsonrpc": "2.0", "id": request["id"], "result": {"roots": [{"uri": uri}]}}));
+        refreshing.await.unwrap();
+
+        assert_eq!(session.index().unwrap().lines, vec!["from the client root"]);
+        let event: Value = serde_json::from_str(&events.recv().await.unwrap().data).unwrap();
+        assert_eq!(event["method"], resources::LIST_CHANGED_NOTIFICATION);
+    }
+}
@@ -0,0 +1,599 @@
+//! MCP sessions: one per stdio connection or Streamable HTTP `Mcp-Session-Id`.
+//!
+//! A session tracks where its client is in the lifecycle and what it negotiated
+//! during `initialize`. For Streamable HTTP it also owns the SSE streams opened by
+//! the client and a bounded history of the events sent on them, so a client that
+//! reconnects with `Last-Event-ID` can pick up where it left off.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Id, Value};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
+
+use crate::cancellation::CancellationToken;
+use crate::lifecycle::{self, Phase};
+use crate::logging::{self, LoggingLevel};
+use crate::WordIndex;
+
+/// Per-request metadata handed to every RPC method: the calling session.
+pub type Meta = Arc<Session>;
+
+/// Number of past events kept per session for `Last-Event-ID` resumption.
+const EVENT_HISTORY_LIMIT: usize = 1024;
+
+pub type StreamId = u64;
+
+/// The stream opened by GET, carrying messages not tied to any client request.
+pub const STANDALONE_STREAM: StreamId = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SseEvent {
+    pub id: u64,
+    pub data: String,
+}
+
+impl SseEvent {
+    /// Formats the event as it is written on a `text/event-stream` body.
+    pub fn to_wire(&self) -> String {
+        format!("id: {}\ndata: {}\n\n", self.id, self.data)
+    }
+}
+
+#[derive(Debug)]
+struct Streams {
+    next_event_id: u64,
+    next_stream_id: StreamId,
+    history: VecDeque<(StreamId, SseEvent)>,
+    live: HashMap<StreamId, mpsc::UnboundedSender<SseEvent>>,
+    /// Request streams whose response has not been sent yet.
+    open: HashSet<StreamId>,
+    /// The stream each POSTed request is answered on, by request id.
+    routes: HashMap<Id, StreamId>,
+}
+
+#[derive(Debug)]
+struct State {
+    phase: Phase,
+    protocol_version: Option<String>,
+    client_capabilities: Value,
+}
+
+/// Server-initiated pings sent to the client.
+#[derive(Debug, Default)]
+struct Pings {
+    next_id: u64,
+    /// The ping awaiting a response, and when it was sent.
+    outstanding: Option<(Value, Instant)>,
+    last_sent: Option<Instant>,
+}
+
+/// Other server-initiated requests awaiting the client's response.
+#[derive(Debug, Default)]
+struct Requests {
+    next_id: u64,
+    pending: HashMap<String, oneshot::Sender<Value>>,
+}
+
+/// What a session's keepalive needs next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PingDue {
+    /// A ping is outstanding or the last one was answered recently.
+    No,
+    Yes,
+    /// The outstanding ping was not answered in time.
+    TimedOut,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    token: CancellationToken,
+    /// The task running the request, when it runs on its own.
+    task: Option<AbortHandle>,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    pub id: String,
+    /// Stateless sessions serve a single plain HTTP request and skip the lifecycle.
+    stateless: bool,
+    state: Mutex<State>,
+    /// Resource URIs the client asked to hear about via `resources/subscribe`.
+    subscriptions: Mutex<HashSet<String>>,
+    /// Least severe level of server log messages the client asked for, if any.
+    log_level: Mutex<Option<LoggingLevel>>,
+    /// Requests still being handled, by JSON-RPC id.
+    in_flight: Mutex<HashMap<Id, InFlight>>,
+    pings: Mutex<Pings>,
+    /// When the client last sent the session anything.
+    last_activity: Mutex<Instant>,
+    requests: Mutex<Requests>,
+    /// The database built from the client's roots, served instead of the shared one.
+    index: Mutex<Option<Arc<WordIndex>>>,
+    streams: Mutex<Streams>,
+}
+
+impl Session {
+    pub fn new(id: String) -> Self {
+        Session {
+            id,
+            stateless: false,
+            state: Mutex::new(State {
+                phase: Phase::AwaitingInitialize,
+                protocol_version: None,
+                client_capabilities: Value::Null,
+            }),
+            subscriptions: Mutex::new(HashSet::new()),
+            log_level: Mutex::new(None),
+            in_flight: Mutex::new(HashMap::new()),
+            pings: Mutex::new(Pings::default()),
+            last_activity: Mutex::new(Instant::now()),
+            requests: Mutex::new(Requests::default()),
+            index: Mutex::new(None),
+            streams: Mutex::new(Streams {
+                next_event_id: 1,
+                next_stream_id: STANDALONE_STREAM + 1,
+                history: VecDeque::new(),
+                live: HashMap::new(),
+                open: HashSet::new(),
+                routes: HashMap::new(),
+            }),
+        }
+    }
+
+    /// A throwaway session for transports without a notion of connection, such as
+    /// plain JSON-RPC over HTTP POST.
+    pub fn stateless() -> Self {
+        Session {
+            stateless: true,
+            ..Session::new("stateless".into())
+        }
+    }
+
+    /// Checks the call against the lifecycle phase of this session.
+    pub fn check_call(&self, method: &str) -> Result<(), Error> {
+        if self.stateless {
+            return Ok(());
+        }
+        lifecycle::check_call(self.state.lock().unwrap().phase, method)
+    }
+
+    /// Records the outcome of a successful `initialize`. Fails if the session has
+    /// already been initialized.
+    pub fn initialize(&self, protocol_version: &str, client_capabilities: Value) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if !self.stateless && state.phase != Phase::AwaitingInitialize {
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: "Session is already initialized.".into(),
+                data: None,
+            });
+        }
+        state.phase = Phase::AwaitingInitialized;
+        state.protocol_version = Some(protocol_version.into());
+        state.client_capabilities = client_capabilities;
+        Ok(())
+    }
+
+    /// Handles the client's `notifications/initialized`.
+    pub fn mark_initialized(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.phase == Phase::AwaitingInitialized {
+            state.phase = Phase::Operating;
+            log::info!(
+                "Session {} operating with protocol version {:?}, client capabilities: {}",
+                self.id,
+                state.protocol_version,
+                state.client_capabilities
+            );
+        } else {
+            log::warn!("Session {} got initialized notification in phase {:?}", self.id, state.phase);
+        }
+    }
+
+    pub fn is_operating(&self) -> bool {
+        self.state.lock().unwrap().phase == Phase::Operating
+    }
+
+    /// Whether the client declared `capability` during `initialize`.
+    pub fn client_supports(&self, capability: &str) -> bool {
+        self.state.lock().unwrap().client_capabilities.get(capability).is_some()
+    }
+
+    /// The database this session serves in place of the shared one, if any.
+    pub fn index(&self) -> Option<Arc<WordIndex>> {
+        self.index.lock().unwrap().clone()
+    }
+
+    /// Replaces the session's own database, returning the previous one.
+    pub fn set_index(&self, index: Option<WordIndex>) -> Option<Arc<WordIndex>> {
+        std::mem::replace(&mut *self.index.lock().unwrap(), index.map(Arc::new))
+    }
+
+    pub fn subscribe(&self, uri: &str) {
+        self.subscriptions.lock().unwrap().insert(uri.into());
+    }
+
+    pub fn unsubscribe(&self, uri: &str) {
+        self.subscriptions.lock().unwrap().remove(uri);
+    }
+
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.subscriptions.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn set_log_level(&self, level: LoggingLevel) {
+        *self.log_level.lock().unwrap() = Some(level);
+    }
+
+    /// Least severe level of log messages the client asked for, if any.
+    pub fn log_level(&self) -> Option<LoggingLevel> {
+        *self.log_level.lock().unwrap()
+    }
+
+    /// Whether a log message at `level` should be forwarded to the client.
+    pub fn wants_log(&self, level: LoggingLevel) -> bool {
+        self.is_operating() && self.log_level.lock().unwrap().is_some_and(|min| level >= min)
+    }
+
+    pub fn track_request(&self, id: Id, token: CancellationToken, task: Option<AbortHandle>) {
+        self.in_flight.lock().unwrap().insert(id, InFlight { token, task });
+    }
+
+    pub fn finish_request(&self, id: &Id) {
+        self.in_flight.lock().unwrap().remove(id);
+    }
+
+    /// Cancels the request with `id`. Returns false if no such request is in flight.
+    pub fn cancel_request(&self, id: &Id) -> bool {
+        let Some(request) = self.in_flight.lock().unwrap().remove(id) else {
+            return false;
+        };
+        request.token.cancel();
+        if let Some(task) = request.task {
+            task.abort();
+        }
+        true
+    }
+
+    /// Whether a ping is due given the keepalive `interval` and `timeout`.
+    pub fn ping_due(&self, interval: Duration, timeout: Duration) -> PingDue {
+        let pings = self.pings.lock().unwrap();
+        match (&pings.outstanding, pings.last_sent) {
+            (Some((_, sent)), _) if sent.elapsed() >= timeout => PingDue::TimedOut,
+            (Some(_), _) => PingDue::No,
+            (None, Some(sent)) if sent.elapsed() < interval => PingDue::No,
+            (None, _) => PingDue::Yes,
+        }
+    }
+
+    /// Records that the client sent the session something.
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// How long the client has been silent, or `None` while a request of its is
+    /// still being handled.
+    pub fn idle_for(&self) -> Option<Duration> {
+        if !self.in_flight.lock().unwrap().is_empty() {
+            return None;
+        }
+        Some(self.last_activity.lock().unwrap().elapsed())
+    }
+
+    /// Sends a `ping` request to the client on the standalone stream.
+    pub fn send_ping(&self) {
+        let id = {
+            let mut pings = self.pings.lock().unwrap();
+            let id = json!(format!("ping-{}", pings.next_id));
+            pings.next_id += 1;
+            let now = Instant::now();
+            pings.outstanding = Some((id.clone(), now));
+            pings.last_sent = Some(now);
+            id
+        };
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": lifecycle::PING});
+        log::trace!("Session {} pinging: {}", self.id, message);
+        self.send(STANDALONE_STREAM, message.to_string());
+    }
+
+    /// Sends a request to the client on the standalone stream. The receiver gets
+    /// the client's whole response message, or is dropped with the session.
+    pub fn request(&self, method: &str, params: Value) -> oneshot::Receiver<Value> {
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut requests = self.requests.lock().unwrap();
+            requests.pending.retain(|_, waiting| !waiting.is_closed());
+            let id = format!("request-{}", requests.next_id);
+            requests.next_id += 1;
+            requests.pending.insert(id.clone(), tx);
+            id
+        };
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        log::debug!("Session {} requesting: {}", self.id, message);
+        self.send(STANDALONE_STREAM, message.to_string());
+        rx
+    }
+
+    /// Handles a JSON-RPC response sent by the client. Returns false if it does
+    /// not answer anything this session asked.
+    pub fn handle_response(&self, response: &Value) -> bool {
+        {
+            let mut pings = self.pings.lock().unwrap();
+            if matches!(&pings.outstanding, Some((id, _)) if response.get("id") == Some(id)) {
+                pings.outstanding = None;
+                return true;
+            }
+        }
+        let waiting = response
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| self.requests.lock().unwrap().pending.remove(id));
+        match waiting {
+            Some(tx) => {
+                // The requester may have given up waiting
+                let _ = tx.send(response.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the client has a stream open for server-initiated messages.
+    pub fn has_standalone(&self) -> bool {
+        self.streams.lock().unwrap().live.contains_key(&STANDALONE_STREAM)
+    }
+
+    pub fn shut_down(&self) {
+        self.state.lock().unwrap().phase = Phase::ShutDown;
+        let in_flight: Vec<Id> = self.in_flight.lock().unwrap().keys().cloned().collect();
+        for id in in_flight {
+            self.cancel_request(&id);
+        }
+        self.close_all();
+        log::debug!("Session {} shut down", self.id);
+    }
+
+    /// Opens a new stream for the response to a POSTed request.
+    pub fn open_stream(&self) -> (StreamId, mpsc::UnboundedReceiver<SseEvent>) {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.next_stream_id;
+        streams.next_stream_id += 1;
+        let (tx, rx) = mpsc::unbounded_channel();
+        streams.live.insert(stream, tx);
+        streams.open.insert(stream);
+        log::trace!("Session {} opened stream {}", self.id, stream);
+        (stream, rx)
+    }
+
+    /// Attaches a fresh GET connection as the standalone stream, replacing any
+    /// previous one.
+    pub fn attach_standalone(&self) -> mpsc::UnboundedReceiver<SseEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.streams.lock().unwrap().live.insert(STANDALONE_STREAM, tx);
+        log::trace!("Session {} attached standalone stream", self.id);
+        rx
+    }
+
+    /// Sends `data` on `stream`, recording it for later resumption. Messages for a
+    /// stream with no live connection are only recorded.
+    pub fn send(&self, stream: StreamId, data: String) {
+        let mut streams = self.streams.lock().unwrap();
+        let event = SseEvent {
+            id: streams.next_event_id,
+            data,
+        };
+        streams.next_event_id += 1;
+        if let Some(tx) = streams.live.get(&stream) {
+            if tx.send(event.clone()).is_err() {
+                log::debug!("Session {} stream {} disconnected", self.id, stream);
+                streams.live.remove(&stream);
+            }
+        }
+        streams.history.push_back((stream, event));
+        if streams.history.len() > EVENT_HISTORY_LIMIT {
+            streams.history.pop_front();
+        }
+    }
+
+    /// Sends a server-initiated JSON-RPC notification on the standalone stream.
+    pub fn notify(&self, method: &str, params: Value) {
+        self.notify_on(STANDALONE_STREAM, method, params);
+    }
+
+    /// Sends a notification about request `id` on the stream its response will
+    /// go out on, or on the standalone stream if it has none.
+    pub fn notify_request(&self, id: Option<&Id>, method: &str, params: Value) {
+        let stream = id
+            .and_then(|id| self.streams.lock().unwrap().routes.get(id).copied())
+            .unwrap_or(STANDALONE_STREAM);
+        self.notify_on(stream, method, params);
+    }
+
+    fn notify_on(&self, stream: StreamId, method: &str, params: Value) {
+        let message = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        log::trace!("Session {} notifying on stream {}: {}", self.id, stream, message);
+        self.send(stream, message.to_string());
+    }
+
+    /// Records that the response to request `id` goes out on `stream`.
+    pub fn route_request(&self, id: Id, stream: StreamId) {
+        self.streams.lock().unwrap().routes.insert(id, stream);
+    }
+
+    /// Marks a request stream as complete and ends its connection.
+    pub fn close_stream(&self, stream: StreamId) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.routes.retain(|_, routed| *routed != stream);
+        streams.live.remove(&stream);
+        streams.open.remove(&stream);
+    }
+
+    /// Resumes the stream that carried `last_event_id`, returning the events sent
+    /// on it since then and a receiver for the ones still to come. The receiver
+    /// ends immediately if the stream has already completed.
+    ///
+    /// Returns `None` if the event is unknown or has dropped out of the history.
+    pub fn resume(&self, last_event_id: u64) -> Option<(Vec<SseEvent>, mpsc::UnboundedReceiver<SseEvent>)> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams
+            .history
+            .iter()
+            .find(|(_, event)| event.id == last_event_id)
+            .map(|(stream, _)| *stream)?;
+        let replay = streams
+            .history
+            .iter()
+            .filter(|(s, event)| *s == stream && event.id > last_event_id)
+            .map(|(_, event)| event.clone())
+            .collect();
+        let (tx, rx) = mpsc::unbounded_channel();
+        if stream == STANDALONE_STREAM || streams.open.contains(&stream) {
+            streams.live.insert(stream, tx);
+        }
+        log::debug!("Session {} resuming stream {} after event {}", self.id, stream, last_event_id);
+        Some((replay, rx))
+    }
+
+    /// Ends every open connection of this session.
+    fn close_all(&self) {
+        self.streams.lock().unwrap().live.clear();
+    }
+}
+
+/// All live sessions, keyed by `Mcp-Session-Id`.
+#[derive(Clone, Debug, Default)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<HashMap<String, Arc<Session>>>>,
+}
+
+impl SessionStore {
+    pub fn create(&self) -> Arc<Session> {
+        let session = Arc::new(Session::new(uuid::Uuid::new_v4().to_string()));
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id.clone(), Arc::clone(&session));
+        log::info!("Created session {}", session.id);
+        session
+    }
+
+    /// Adds a session created by a transport other than Streamable HTTP, so that
+    /// broadcasts reach it too.
+    pub fn register(&self, session: Arc<Session>) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id.clone(), session);
+    }
+
+    pub fn all(&self) -> Vec<Arc<Session>> {
+        self.sessions.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Session>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn remove(&self, id: &str) -> Option<Arc<Session>> {
+        let session = self.sessions.lock().unwrap().remove(id)?;
+        session.shut_down();
+        logging::refresh_client_level(self);
+        log::info!("Terminated session {}", id);
+        Some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_records_history_and_delivers_live() {
+        let session = Session::new("s".into());
+        let mut rx = session.attach_standalone();
+        session.send(STANDALONE_STREAM, "a".into());
+        let (stream, _stream_rx) = session.open_stream();
+        session.send(stream, "b".into());
+        session.send(STANDALONE_STREAM, "c".into());
+
+        assert_eq!(rx.try_recv().unwrap(), SseEvent { id: 1, data: "a".into() });
+        assert_eq!(rx.try_recv().unwrap(), SseEvent { id: 3, data: "c".into() });
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_resume_replays_only_the_same_stream() {
+        let session = Session::new("s".into());
+        let (stream, rx) = session.open_stream();
+        drop(rx);
+        session.send(stream, "first".into());
+        session.notify("notifications/message", json!({}));
+        session.send(stream, "second".into());
+        session.close_stream(stream);
+
+        let (replay, mut rx) = session.resume(1).expect("event 1 should be known");
+        assert_eq!(replay, vec![SseEvent { id: 3, data: "second".into() }]);
+        assert!(rx.try_recv().is_err(), "closed stream should not stay live");
+        assert!(session.streams.lock().unwrap().open.is_empty(), "closed streams should be forgotten");
+
+        assert!(session.resume(99).is_none());
+    }
+
+    #[test]
+    fn test_resume_reattaches_open_streams() {
+        let session = Session::new("s".into());
+        let (stream, rx) = session.open_stream();
+        drop(rx);
+        session.send(stream, "first".into());
+
+        let (replay, mut rx) = session.resume(1).expect("event 1 should be known");
+        assert!(replay.is_empty());
+        session.send(stream, "second".into());
+        assert_eq!(rx.try_recv().unwrap(), SseEvent { id: 2, data: "second".into() });
+    }
+
+    #[test]
+    fn test_lifecycle_transitions() {
+        let session = Session::new("s".into());
+        assert!(session.check_call("tools/list").is_err());
+        session.initialize("2025-06-18", serde_json::json!({"roots": {}})).unwrap();
+        assert!(session.initialize("2025-06-18", Value::Null).is_err());
+        session.mark_initialized();
+        assert!(session.check_call("tools/list").is_ok());
+        session.shut_down();
+        assert!(session.check_call("tools/list").is_err());
+
+        let stateless = Session::stateless();
+        assert!(stateless.check_call("search").is_ok());
+        assert!(stateless.initialize("2025-06-18", Value::Null).is_ok());
+    }
+
+    #[test]
+    fn test_requests_are_matched_to_responses() {
+        let session = Session::new("s".into());
+        let mut events = session.attach_standalone();
+        let mut response = session.request("roots/list", json!({}));
+        let request: Value = serde_json::from_str(&events.try_recv().unwrap().data).unwrap();
+        assert_eq!(request["method"], "roots/list");
+
+        assert!(!session.handle_response(&json!({"jsonrpc": "2.0", "id": "other", "result": {}})));
+        let answer = json!({"jsonrpc": "2.0", "id": request["id"], "result": {"roots": []}});
+        assert!(session.handle_response(&answer));
+        assert_eq!(response.try_recv().unwrap(), answer);
+        assert!(!session.handle_response(&answer), "each request is answered once");
+    }
+
+    #[test]
+    fn test_store_create_get_remove() {
+        let store = SessionStore::default();
+        let session = store.create();
+        assert!(store.get(&session.id).is_some());
+        assert!(store.remove(&session.id).is_some());
+        assert!(store.get(&session.id).is_none());
+        assert!(store.remove(&session.id).is_none());
+    }
+}
@@ -0,0 +1,177 @@
+//! Newline-delimited JSON-RPC over stdin/stdout, as used by MCP hosts that launch
+//! the server as a subprocess.
+//!
+//! stdout carries protocol messages only; all logging goes to stderr. The whole
+//! connection is a single session.
+
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::lifecycle::Handler;
+use crate::message;
+use crate::session::{Session, SessionStore};
+
+pub const SESSION_ID: &str = "stdio";
+
+/// Reads one JSON-RPC message per line from `reader` and writes each response, and
+/// every notification the server sends the session, as a single line to `writer`.
+/// Returns once `reader` reaches EOF.
+pub async fn serve<R, W>(handler: Handler, sessions: SessionStore, reader: R, mut writer: W) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let session = Arc::new(Session::new(SESSION_ID.into()));
+    sessions.register(Arc::clone(&session));
+    let result = pump(&handler, &session, reader, &mut writer).await;
+    sessions.remove(&session.id);
+    result
+}
+
+async fn pump<R, W>(handler: &Handler, session: &Arc<Session>, reader: R, writer: &mut W) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut notifications = session.attach_standalone();
+    let mut lines = BufReader::new(reader).lines();
+    // Once the session is operating, requests are answered concurrently so that a
+    // slow one can still be cancelled. The sender is dropped at EOF, and the pump
+    // returns when every request it handed out has been answered.
+    let (responses_tx, mut responses) = mpsc::unbounded_channel::<String>();
+    let mut responses_tx = Some(responses_tx);
+    loop {
+        tokio::select! {
+            line = lines.next_line(), if responses_tx.is_some() => {
+                let Some(line) = line? else {
+                    log::debug!("stdio input reached EOF.");
+                    responses_tx = None;
+                    continue;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                log::trace!("stdio received: {}", line);
+                session.touch();
+                let incoming = message::split(&line);
+                for response in &incoming.responses {
+                    if !session.handle_response(response) {
+                        log::debug!("Ignoring unexpected response on stdio: {}", response);
+                    }
+                }
+                if let Some(error) = incoming.error {
+                    write_line(writer, &error).await?;
+                }
+                let Some(line) = incoming.rest else {
+                    continue;
+                };
+                if session.is_operating() {
+                    let handler = handler.clone();
+                    let session = Arc::clone(session);
+                    let responses_tx = responses_tx.clone();
+                    tokio::spawn(async move {
+                        if let (Some(response), Some(tx)) = (handler.handle_request(&line, session).await, responses_tx) {
+                            let _ = tx.send(response);
+                        }
+                    });
+                    continue;
+                }
+                match handler.handle_request(&line, Arc::clone(session)).await {
+                    Some(response) => write_line(writer, &response).await?,
+                    None => log::trace!("No response for stdio message (notification)."),
+                }
+            }
+            response = responses.recv() => match response {
+                Some(response) => write_line(writer, &response).await?,
+                None => return Ok(()),
+            },
+            event = notifications.recv() => match event {
+                Some(event) => write_line(writer, &event.data).await?,
+                None => {
+                    log::info!("stdio session was closed by the server.");
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, message: &str) -> std::io::Result<()> {
+    log::trace!("stdio sending: {}", message);
+    writer.write_all(message.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::session::Meta;
+    use jsonrpc_http_server::jsonrpc_core::{Params, Value};
+
+    #[tokio::test]
+    async fn test_stdio_serves_until_eof() {
+        let mut handler = lifecycle::new_handler();
+        handler.add_method_with_meta("initialize", |_params: Params, session: Meta| async move {
+            session.initialize("2025-06-18", Value::Null)?;
+            Ok(Value::Null)
+        });
+        handler.add_sync_method("echo", |params: Params| params.parse::<Value>());
+        handler.add_notification("notify", |_| {});
+        handler.add_notification_with_meta("notifications/initialized", |_params: Params, session: Meta| {
+            session.mark_initialized()
+        });
+
+        let input = concat!(
+            r#"{"jsonrpc": "2.0", "method": "initialize", "params": {}, "id": 0}"#,
+            "\n",
+            r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#,
+            "\n",
+            r#"{"jsonrpc": "2.0", "method": "echo", "params": ["a"], "id": 1}"#,
+            "\n\n",
+            r#"{"jsonrpc": "2.0", "method": "notify"}"#,
+            "\n",
+            r#"{"jsonrpc": "2.0", "method": "echo", "params": ["b"], "id": 2}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        serve(handler, SessionStore::default(), input.as_bytes(), &mut output)
+            .await
+            .expect("serve should succeed");
+
+        let output = String::from_utf8(output).unwrap();
+        let responses: Vec<Value> = output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(responses.len(), 3, "Notifications should not produce output");
+        assert_eq!(responses[1]["id"], 1);
+        assert_eq!(responses[1]["result"][0], "a");
+        assert_eq!(responses[2]["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_stdio_forwards_notifications() {
+        let sessions = SessionStore::default();
+        let (client_in, server_in) = tokio::io::duplex(1024);
+        let (server_out, client_out) = tokio::io::duplex(1024);
+        let handler = lifecycle::new_handler();
+        let task = tokio::spawn(serve(handler, sessions.clone(), server_in, server_out));
+
+        let session = loop {
+            match sessions.get(SESSION_ID) {
+                Some(session) => break session,
+                None => tokio::task::yield_now().await,
+            }
+        };
+        session.notify("notifications/resources/list_changed", serde_json::json!({}));
+        let mut lines = BufReader::new(client_out).lines();
+        let line = lines.next_line().await.unwrap().expect("notification should be written");
+        let message: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(message["method"], "notifications/resources/list_changed");
+
+        drop(client_in);
+        task.await.unwrap().expect("serve should end cleanly on EOF");
+        assert!(sessions.get(SESSION_ID).is_none(), "session should be dropped on EOF");
+    }
+}
@@ -0,0 +1,525 @@
+//! The MCP Streamable HTTP transport.
+//!
+//! A single endpoint accepts POSTed JSON-RPC messages, answering with either a
+//! plain JSON body or a `text/event-stream`, and GET requests that open a
+//! long-lived SSE stream for server-initiated messages. Sessions are assigned on
+//! `initialize` via the `Mcp-Session-Id` header and ended with DELETE.
+//!
+//! Requests sent by browsers carry an `Origin`, which must be a localhost one
+//! unless other origins are allowed, so that web pages cannot reach a local
+//! server through DNS rebinding. Requests without one are accepted.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use jsonrpc_http_server::hyper::header::{self, HeaderValue};
+use jsonrpc_http_server::hyper::service::{make_service_fn, service_fn};
+use jsonrpc_http_server::hyper::{self, Body, Method, Request, Response, Server, StatusCode};
+use jsonrpc_http_server::jsonrpc_core::Id;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::lifecycle::Handler;
+use crate::message::{self, is_request, messages};
+use crate::plain_http;
+use crate::session::{Session, SessionStore, SseEvent};
+use crate::SUPPORTED_PROTOCOL_VERSIONS;
+
+pub const ENDPOINT_PATH: &str = "/mcp";
+pub const SESSION_ID_HEADER: &str = "mcp-session-id";
+const PROTOCOL_VERSION_HEADER: &str = "mcp-protocol-version";
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+const EVENT_STREAM: &str = "text/event-stream";
+
+/// Hosts whose origins are accepted when no others are allowed.
+const LOCALHOSTS: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+
+pub struct StreamableHttp {
+    handler: Handler,
+    sessions: SessionStore,
+    /// Origins accepted instead of the localhost ones, when not empty.
+    allowed_origins: Vec<String>,
+}
+
+impl StreamableHttp {
+    pub fn new(handler: Handler, sessions: SessionStore) -> Self {
+        StreamableHttp {
+            handler,
+            sessions,
+            allowed_origins: Vec::new(),
+        }
+    }
+
+    /// Accepts requests from exactly `origins`, such as `https://example.com`,
+    /// instead of from localhost origins.
+    pub fn with_allowed_origins(self, origins: Vec<String>) -> Self {
+        StreamableHttp {
+            allowed_origins: origins,
+            ..self
+        }
+    }
+
+    fn origin_allowed(&self, origin: &str) -> bool {
+        if !self.allowed_origins.is_empty() {
+            return self.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin));
+        }
+        origin
+            .parse::<hyper::Uri>()
+            .ok()
+            .and_then(|uri| uri.host().map(str::to_ascii_lowercase))
+            .is_some_and(|host| LOCALHOSTS.contains(&host.as_str()))
+    }
+
+    pub async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        log::debug!("Streamable HTTP {} {}", req.method(), req.uri());
+        if req.uri().path() != ENDPOINT_PATH {
+            return error_response(StatusCode::NOT_FOUND, "Not found");
+        }
+        if let Some(origin) = req.headers().get(header::ORIGIN) {
+            if !origin.to_str().is_ok_and(|origin| self.origin_allowed(origin)) {
+                log::warn!("Rejecting request from origin {:?}", origin);
+                return error_response(StatusCode::FORBIDDEN, "Origin not allowed");
+            }
+        }
+        if let Some(version) = header_str(&req, PROTOCOL_VERSION_HEADER) {
+            if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+                log::warn!("Rejecting request with unsupported MCP-Protocol-Version {}", version);
+                return error_response(StatusCode::BAD_REQUEST, "Unsupported MCP-Protocol-Version");
+            }
+        }
+        match *req.method() {
+            Method::POST => self.handle_post(req).await,
+            Method::GET => self.handle_get(req),
+            Method::DELETE => self.handle_delete(req),
+            _ => {
+                let mut response = error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+                response
+                    .headers_mut()
+                    .insert(header::ALLOW, HeaderValue::from_static("GET, POST, DELETE"));
+                response
+            }
+        }
+    }
+
+    async fn handle_post(&self, req: Request<Body>) -> Response<Body> {
+        let wants_sse = accepts_event_stream(&req);
+        let session_id = header_str(&req, SESSION_ID_HEADER).map(str::to_owned);
+
+        let body = match plain_http::read_body(req.into_body(), plain_http::MAX_BODY_SIZE).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large"),
+            Err(e) => {
+                log::error!("Failed to read request body: {}", e);
+                return error_response(StatusCode::BAD_REQUEST, "Failed to read request body");
+            }
+        };
+        let body = match String::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Request body is not valid UTF-8"),
+        };
+        let message: Value = match serde_json::from_str(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Failed to parse POSTed message: {}", e);
+                return json_response(
+                    StatusCode::BAD_REQUEST,
+                    json!({"jsonrpc": "2.0", "error": {"code": -32700, "message": "Parse error"}, "id": null})
+                        .to_string(),
+                );
+            }
+        };
+
+        let is_initialize = messages(&message).any(|m| m.get("method") == Some(&json!("initialize")));
+        let (session, new_session) = match (is_initialize, session_id.as_deref()) {
+            (true, None) => (self.sessions.create(), true),
+            // An initialize naming a session goes to it, to be rejected as a repeat
+            (_, id) => match self.session_for(id) {
+                Ok(session) => (session, false),
+                Err((status, message)) => return error_response(status, message),
+            },
+        };
+
+        let incoming = message::split(&body);
+        for response in &incoming.responses {
+            if !session.handle_response(response) {
+                log::debug!("Ignoring unexpected response from session {}: {}", session.id, response);
+            }
+        }
+        if let Some(error) = incoming.error {
+            return json_response(StatusCode::BAD_REQUEST, error);
+        }
+        let Some(body) = incoming.rest else {
+            return empty_response(StatusCode::ACCEPTED);
+        };
+        if !incoming.has_requests {
+            // Notifications only: nothing to answer with, unless they were invalid.
+            return match self.handler.handle_request(&body, session).await {
+                Some(output) => json_response(StatusCode::BAD_REQUEST, output),
+                None => empty_response(StatusCode::ACCEPTED),
+            };
+        }
+
+        let mut response = if wants_sse {
+            let (stream, rx) = session.open_stream();
+            // Notifications about these requests, such as progress, share their stream.
+            for id in messages(&message).filter(|m| is_request(m)).filter_map(|m| m.get("id")) {
+                if let Ok(id) = serde_json::from_value::<Id>(id.clone()) {
+                    session.route_request(id, stream);
+                }
+            }
+            let session = Arc::clone(&session);
+            let handler = self.handler.clone();
+            let sessions = self.sessions.clone();
+            tokio::spawn(async move {
+                if let Some(output) = handler.handle_request(&body, Arc::clone(&session)).await {
+                    if new_session && is_error_response(&output) {
+                        sessions.remove(&session.id);
+                    }
+                    session.send(stream, output);
+                }
+                session.close_stream(stream);
+            });
+            sse_response(Vec::new(), rx)
+        } else {
+            match self.handler.handle_request(&body, Arc::clone(&session)).await {
+                Some(output) => {
+                    if new_session && is_error_response(&output) {
+                        self.sessions.remove(&session.id);
+                    }
+                    json_response(StatusCode::OK, output)
+                }
+                None => empty_response(StatusCode::ACCEPTED),
+            }
+        };
+
+        if new_session {
+            if let Ok(value) = HeaderValue::from_str(&session.id) {
+                response.headers_mut().insert(SESSION_ID_HEADER, value);
+            }
+        }
+        response
+    }
+
+    fn handle_get(&self, req: Request<Body>) -> Response<Body> {
+        if !accepts_event_stream(&req) {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "GET requires Accept: text/event-stream");
+        }
+        let session = match self.session_for(header_str(&req, SESSION_ID_HEADER)) {
+            Ok(session) => session,
+            Err((status, message)) => return error_response(status, message),
+        };
+
+        let resumed = header_str(&req, LAST_EVENT_ID_HEADER)
+            .and_then(|id| id.trim().parse::<u64>().ok())
+            .and_then(|last_event_id| session.resume(last_event_id));
+        match resumed {
+            Some((replay, rx)) => sse_response(replay, rx),
+            None => sse_response(Vec::new(), session.attach_standalone()),
+        }
+    }
+
+    fn handle_delete(&self, req: Request<Body>) -> Response<Body> {
+        match header_str(&req, SESSION_ID_HEADER) {
+            None => error_response(StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"),
+            Some(id) => match self.sessions.remove(id) {
+                Some(_) => empty_response(StatusCode::OK),
+                None => error_response(StatusCode::NOT_FOUND, "Session not found"),
+            },
+        }
+    }
+
+    fn session_for(&self, session_id: Option<&str>) -> Result<Arc<Session>, (StatusCode, &'static str)> {
+        let id = session_id.ok_or((StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"))?;
+        let session = self
+            .sessions
+            .get(id)
+            .ok_or((StatusCode::NOT_FOUND, "Session not found"))?;
+        session.touch();
+        Ok(session)
+    }
+}
+
+/// Binds `addr` and serves `transport` on it in a background task.
+pub fn start(addr: &SocketAddr, transport: Arc<StreamableHttp>) -> Result<tokio::task::JoinHandle<()>, hyper::Error> {
+    let make_service = make_service_fn(move |_conn| {
+        let transport = Arc::clone(&transport);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let transport = Arc::clone(&transport);
+                async move { Ok::<_, Infallible>(transport.handle(req).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(addr)?.serve(make_service);
+    Ok(tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Streamable HTTP server error: {}", e);
+        }
+    }))
+}
+
+fn is_error_response(output: &str) -> bool {
+    serde_json::from_str::<Value>(output)
+        .map(|v| v.get("error").is_some())
+        .unwrap_or(false)
+}
+
+fn header_str<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn accepts_event_stream(req: &Request<Body>) -> bool {
+    header_str(req, header::ACCEPT.as_str()).is_some_and(|accept| accept.contains(EVENT_STREAM))
+}
+
+fn sse_response(replay: Vec<SseEvent>, mut rx: mpsc::UnboundedReceiver<SseEvent>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        for event in replay {
+            if sender.send_data(event.to_wire().into()).await.is_err() {
+                return;
+            }
+        }
+        while let Some(event) = rx.recv().await {
+            if sender.send_data(event.to_wire().into()).await.is_err() {
+                log::debug!("SSE client disconnected before event {}", event.id);
+                return;
+            }
+        }
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, EVENT_STREAM)
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .expect("static SSE response parts are valid")
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("static JSON response parts are valid")
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        json!({"jsonrpc": "2.0", "error": {"code": -32000, "message": message}, "id": null}).to_string(),
+    )
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("static empty response parts are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use hyper::body::HttpBody;
+    use crate::session::{Meta, STANDALONE_STREAM};
+    use jsonrpc_http_server::jsonrpc_core::{Params, Value};
+
+    fn transport() -> StreamableHttp {
+        let mut handler = lifecycle::new_handler();
+        handler.add_method_with_meta("initialize", |_params: Params, session: Meta| async move {
+            session.initialize("2025-06-18", Value::Null)?;
+            Ok(json!({"capabilities": {}}))
+        });
+        handler.add_sync_method("echo", |params: Params| params.parse::<Value>());
+        handler.add_notification_with_meta("notifications/initialized", |_params: Params, session: Meta| {
+            session.mark_initialized()
+        });
+        StreamableHttp::new(handler, SessionStore::default())
+    }
+
+    fn post(body: &str, session_id: Option<&str>, accept: &str) -> Request<Body> {
+        let mut builder = Request::post(ENDPOINT_PATH).header(header::ACCEPT, accept);
+        if let Some(id) = session_id {
+            builder = builder.header(SESSION_ID_HEADER, id);
+        }
+        builder.body(Body::from(body.to_owned())).unwrap()
+    }
+
+    async fn initialize(transport: &StreamableHttp) -> String {
+        let response = transport
+            .handle(post(
+                r#"{"jsonrpc": "2.0", "method": "initialize", "params": {}, "id": 1}"#,
+                None,
+                "application/json",
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let session_id = response
+            .headers()
+            .get(SESSION_ID_HEADER)
+            .expect("initialize should assign a session id")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let initialized = r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#;
+        let response = transport.handle(post(initialized, Some(&session_id), "application/json")).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        session_id
+    }
+
+    async fn body_string(response: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_post_requires_known_session() {
+        let transport = transport();
+        let echo = r#"{"jsonrpc": "2.0", "method": "echo", "params": [1], "id": 2}"#;
+
+        let response = transport.handle(post(echo, None, "application/json")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = transport.handle(post(echo, Some("unknown"), "application/json")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let session_id = initialize(&transport).await;
+        let response = transport.handle(post(echo, Some(&session_id), "application/json")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["result"][0], 1);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_initialize_is_rejected_by_the_session() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let initialize = r#"{"jsonrpc": "2.0", "method": "initialize", "params": {}, "id": 2}"#;
+
+        let response = transport.handle(post(initialize, Some(&session_id), "application/json")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(SESSION_ID_HEADER).is_none(), "no new session is created");
+        let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert!(body["error"].is_object());
+        assert!(transport.sessions.get(&session_id).is_some(), "the session survives");
+
+        let response = transport.handle(post(initialize, Some("unknown"), "application/json")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_disallowed_origins() {
+        let echo = |session_id: &str, origin: &str| {
+            let echo = r#"{"jsonrpc": "2.0", "method": "echo", "params": [1], "id": 2}"#;
+            let mut request = post(echo, Some(session_id), "application/json");
+            request.headers_mut().insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+            request
+        };
+
+        let transport = transport();
+        let id = initialize(&transport).await;
+        assert_eq!(transport.handle(echo(&id, "http://localhost:6274")).await.status(), StatusCode::OK);
+        assert_eq!(transport.handle(echo(&id, "http://127.0.0.1")).await.status(), StatusCode::OK);
+        assert_eq!(transport.handle(echo(&id, "http://[::1]:8080")).await.status(), StatusCode::OK);
+        assert_eq!(transport.handle(echo(&id, "http://evil.example")).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(transport.handle(echo(&id, "null")).await.status(), StatusCode::FORBIDDEN);
+
+        let transport = transport.with_allowed_origins(vec!["https://app.example".into()]);
+        assert_eq!(transport.handle(echo(&id, "https://app.example")).await.status(), StatusCode::OK);
+        assert_eq!(transport.handle(echo(&id, "http://localhost:6274")).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_post_rejects_oversized_body() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let padding = " ".repeat(plain_http::MAX_BODY_SIZE);
+        let echo = format!(r#"{{"jsonrpc": "2.0", "method": "echo", "params": [1], "id": 2}}{}"#, padding);
+        let response = transport.handle(post(&echo, Some(&session_id), "application/json")).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unsupported_protocol_version_header() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let request = |version: &str| {
+            Request::post(ENDPOINT_PATH)
+                .header(SESSION_ID_HEADER, session_id.as_str())
+                .header(PROTOCOL_VERSION_HEADER, version)
+                .body(Body::from(r#"{"jsonrpc": "2.0", "method": "echo", "params": [], "id": 2}"#))
+                .unwrap()
+        };
+        assert_eq!(transport.handle(request("2024-11-05")).await.status(), StatusCode::OK);
+        assert_eq!(transport.handle(request("0.1")).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_post_notification_is_accepted() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let response = transport
+            .handle(post(
+                r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#,
+                Some(&session_id),
+                "application/json, text/event-stream",
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(body_string(response).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_post_answers_with_event_stream() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let response = transport
+            .handle(post(
+                r#"{"jsonrpc": "2.0", "method": "echo", "params": ["hi"], "id": 3}"#,
+                Some(&session_id),
+                "application/json, text/event-stream",
+            ))
+            .await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], EVENT_STREAM);
+        let body = body_string(response).await;
+        let data = body
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .expect("stream should carry a data line");
+        let message: Value = serde_json::from_str(data).unwrap();
+        assert_eq!(message["id"], 3);
+        assert_eq!(message["result"][0], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_get_stream_resumes_after_last_event_id() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let session = transport.sessions.get(&session_id).unwrap();
+        session.send(STANDALONE_STREAM, "one".into());
+        session.send(STANDALONE_STREAM, "two".into());
+
+        let request = Request::get(ENDPOINT_PATH)
+            .header(header::ACCEPT, EVENT_STREAM)
+            .header(SESSION_ID_HEADER, session_id.as_str())
+            .header(LAST_EVENT_ID_HEADER, "1")
+            .body(Body::empty())
+            .unwrap();
+        let mut body = transport.handle(request).await.into_body();
+        let chunk = body.data().await.unwrap().unwrap();
+        assert_eq!(chunk, "id: 2\ndata: two\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_delete_terminates_session() {
+        let transport = transport();
+        let session_id = initialize(&transport).await;
+        let delete = |id: &str| {
+            Request::delete(ENDPOINT_PATH)
+                .header(SESSION_ID_HEADER, id)
+                .body(Body::empty())
+                .unwrap()
+        };
+        assert_eq!(transport.handle(delete(&session_id)).await.status(), StatusCode::OK);
+        assert_eq!(transport.handle(delete(&session_id)).await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,829 @@
+//! MCP tool wrappers around the word index, exposed via `tools/list` and `tools/call`.
+//!
+//! Besides the built-in search and fetch tools, a [`ToolRegistry`] holds saved
+//! searches: named tools running a fixed query, added and removed at runtime from
+//! the `--tools` file or by admin calls. Sessions are told when that set changes.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use jsonrpc_http_server::jsonrpc_core::{Error, ErrorCode, Params, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::cancellation;
+use crate::connector::Connector;
+use crate::lifecycle::Handler;
+use crate::progress::Progress;
+use crate::query::{self, ParseError, Query, SearchError};
+use crate::ranking::{self, Sort};
+use crate::resources::TextResourceContents;
+use crate::session::{Meta, SessionStore};
+use crate::watch::SharedIndex;
+use crate::{SearchOptions, WordIndex};
+
+pub const SEARCH_TOOL: &str = "search";
+pub const FETCH_TOOL: &str = "fetch";
+pub const LIST_CHANGED_NOTIFICATION: &str = "notifications/tools/list_changed";
+
+/// Hits the search tools return when not told how many.
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+/// Most hits the search tools return at once.
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<Value>,
+    pub annotations: ToolAnnotations,
+}
+
+/// Hints about a tool's behaviour, letting hosts e.g. auto-approve read-only tools.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destructive_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_world_hint: Option<bool>,
+}
+
+impl ToolAnnotations {
+    /// Annotations for a tool that only reads the local database.
+    pub fn read_only(title: &str) -> Self {
+        ToolAnnotations {
+            title: Some(title.into()),
+            read_only_hint: Some(true),
+            destructive_hint: None,
+            idempotent_hint: Some(true),
+            open_world_hint: Some(false),
+        }
+    }
+
+    /// These annotations with every hint set in `other` taking precedence.
+    fn overridden_by(self, other: &ToolAnnotations) -> Self {
+        ToolAnnotations {
+            title: other.title.clone().or(self.title),
+            read_only_hint: other.read_only_hint.or(self.read_only_hint),
+            destructive_hint: other.destructive_hint.or(self.destructive_hint),
+            idempotent_hint: other.idempotent_hint.or(self.idempotent_hint),
+            open_world_hint: other.open_world_hint.or(self.open_world_hint),
+        }
+    }
+}
+
+/// Reads per-tool annotation overrides from a JSON object keyed by tool name.
+pub fn load_annotations(path: impl AsRef<Path>) -> std::io::Result<HashMap<String, ToolAnnotations>> {
+    let file = std::fs::File::open(path)?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListToolsResult {
+    tools: Vec<Tool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CallToolParams {
+    name: String,
+    #[serde(default)]
+    arguments: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchArguments {
+    query: String,
+    #[serde(default)]
+    sort: Sort,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    DEFAULT_SEARCH_LIMIT
+}
+
+fn limit_schema() -> Value {
+    json!({
+        "type": "integer",
+        "minimum": 1,
+        "maximum": MAX_SEARCH_LIMIT,
+        "default": DEFAULT_SEARCH_LIMIT,
+        "description": "Most hits to return, taking the best matches."
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct FetchArguments {
+    line: usize,
+}
+
+/// An MCP content block, as used in tool results and prompt messages.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Content {
+    Text { text: String },
+    Resource { resource: TextResourceContents },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CallToolResult {
+    pub content: Vec<Content>,
+    /// The result as JSON matching the tool's `outputSchema`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structured_content: Option<Value>,
+    pub is_error: bool,
+}
+
+impl CallToolResult {
+    pub fn structured(text: String, structured_content: Value) -> Self {
+        CallToolResult {
+            content: vec![Content::Text { text }],
+            structured_content: Some(structured_content),
+            is_error: false,
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        CallToolResult {
+            content: vec![Content::Text { text: message }],
+            structured_content: None,
+            is_error: true,
+        }
+    }
+}
+
+/// A database line in structured tool output.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Hit {
+    /// Stable identifier of the line.
+    pub id: String,
+    pub line: usize,
+    pub text: String,
+    /// How well the line matches the query; only set for search results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+impl Hit {
+    pub fn id_for(line: usize) -> String {
+        format!("line-{}", line)
+    }
+}
+
+fn hit_schema(with_score: bool) -> Value {
+    let mut schema = json!({
+        "type": "object",
+        "properties": {
+            "id": {"type": "string", "description": "Stable identifier of the line."},
+            "line": {"type": "integer", "minimum": 0, "description": "Zero-based line number."},
+            "text": {"type": "string", "description": "The text of the line."}
+        },
+        "required": ["id", "line", "text"]
+    });
+    if with_score {
+        schema["properties"]["score"] = json!({"type": "number", "description": "Relevance of the line to the query; higher is better."});
+        schema["required"] = json!(["id", "line", "text", "score"]);
+    }
+    schema
+}
+
+fn search_output_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "hits": {"type": "array", "items": hit_schema(true)},
+            "total": {"type": "integer", "minimum": 0, "description": "Number of lines matching the query."},
+            "truncated": {"type": "boolean", "description": "Whether matching lines were left out to keep to the limit."}
+        },
+        "required": ["hits", "total", "truncated"]
+    })
+}
+
+pub fn list_tools() -> Vec<Tool> {
+    vec![
+        Tool {
+            name: SEARCH_TOOL.into(),
+            description: "Search the database for lines containing all of the given words, best matches first.".into(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Whitespace-separated words that must all appear on a line. Quote words, as in \"empty line\", to match them as an exact phrase; combine terms with OR, exclude them with NOT or -term, and group them with parentheses. In words, * matches any characters and ? exactly one, as in test* or te?t; word~1 or word~2 also matches words one or two typos away, and word~ picks the distance by length."
+                    },
+                    "sort": {
+                        "type": "string",
+                        "enum": ["relevance", "line"],
+                        "default": "relevance",
+                        "description": "Order of the hits: by relevance score, or by line number."
+                    },
+                    "limit": limit_schema()
+                },
+                "required": ["query"]
+            }),
+            output_schema: Some(search_output_schema()),
+            annotations: ToolAnnotations::read_only("Search records"),
+        },
+        Tool {
+            name: FETCH_TOOL.into(),
+            description: "Fetch a single line of the database by its zero-based line number.".into(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "line": {
+                        "type": "integer",
+                        "minimum": 0,
+                        "description": "Zero-based line number, as returned by the search tool."
+                    }
+                },
+                "required": ["line"]
+            }),
+            output_schema: Some(hit_schema(false)),
+            annotations: ToolAnnotations::read_only("Fetch record"),
+        },
+    ]
+}
+
+/// A tool running a fixed search, optionally narrowed by extra words.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedSearch {
+    pub name: String,
+    pub description: String,
+    pub query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
+}
+
+impl SavedSearch {
+    fn tool(&self) -> Tool {
+        Tool {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": format!("Extra words to require on top of '{}'.", self.query)
+                    },
+                    "limit": limit_schema()
+                }
+            }),
+            output_schema: Some(search_output_schema()),
+            annotations: match &self.annotations {
+                Some(annotations) => ToolAnnotations::read_only(&self.name).overridden_by(annotations),
+                None => ToolAnnotations::read_only(&self.name),
+            },
+        }
+    }
+
+    /// The search tool arguments running this saved search with `arguments`,
+    /// requiring both the saved query and the extra words to match.
+    fn search_arguments(&self, arguments: &Value) -> Result<Value, ParseError> {
+        let extra = arguments.get("query").and_then(Value::as_str).unwrap_or("");
+        // Extra words that parse on their own have balanced parentheses, so they
+        // cannot reach into the saved query's group
+        let query = match query::parse(extra)? {
+            Query::Or(any) if any.is_empty() => self.query.clone(),
+            _ => format!("({}) ({})", self.query, extra),
+        };
+        let mut search = json!({ "query": query });
+        if let Some(limit) = arguments.get("limit") {
+            search["limit"] = limit.clone();
+        }
+        Ok(search)
+    }
+}
+
+/// The tools currently offered: the built-in ones plus the saved searches.
+#[derive(Clone, Debug, Default)]
+pub struct ToolRegistry {
+    saved: Arc<RwLock<Vec<SavedSearch>>>,
+    /// Configured annotations, by tool name, overriding the tools' own.
+    annotations: Arc<HashMap<String, ToolAnnotations>>,
+    /// Serve the built-in tools under the connector contract instead.
+    connector: Option<Connector>,
+    /// How the search tools run their queries.
+    search: SearchOptions,
+}
+
+impl ToolRegistry {
+    pub fn with_annotations(annotations: HashMap<String, ToolAnnotations>) -> Self {
+        ToolRegistry {
+            annotations: Arc::new(annotations),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_connector(self, connector: Connector) -> Self {
+        ToolRegistry {
+            connector: Some(connector),
+            ..self
+        }
+    }
+
+    pub fn with_search_options(self, search: SearchOptions) -> Self {
+        ToolRegistry { search, ..self }
+    }
+
+    fn builtin_tools(&self) -> Vec<Tool> {
+        match &self.connector {
+            Some(connector) => connector.tools(),
+            None => list_tools(),
+        }
+    }
+
+    pub fn tools(&self) -> Vec<Tool> {
+        let mut tools = self.builtin_tools();
+        tools.extend(self.saved.read().unwrap().iter().map(SavedSearch::tool));
+        for tool in &mut tools {
+            if let Some(annotations) = self.annotations.get(&tool.name) {
+                tool.annotations = std::mem::take(&mut tool.annotations).overridden_by(annotations);
+            }
+        }
+        tools
+    }
+
+    pub fn saved_search(&self, name: &str) -> Option<SavedSearch> {
+        self.saved.read().unwrap().iter().find(|s| s.name == name).cloned()
+    }
+
+    /// Adds `saved`, replacing any saved search of the same name. Returns whether
+    /// the tool list changed.
+    pub fn add(&self, saved: SavedSearch) -> Result<bool, Error> {
+        if self.builtin_tools().iter().any(|tool| tool.name == saved.name) {
+            return Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: format!("Tool '{}' is built in and cannot be replaced", saved.name),
+                data: None,
+            });
+        }
+        let mut all = self.saved.write().unwrap();
+        if all.contains(&saved) {
+            return Ok(false);
+        }
+        all.retain(|s| s.name != saved.name);
+        all.push(saved);
+        Ok(true)
+    }
+
+    /// Removes the saved search `name`. Returns whether there was one.
+    pub fn remove(&self, name: &str) -> bool {
+        let mut all = self.saved.write().unwrap();
+        let before = all.len();
+        all.retain(|s| s.name != name);
+        all.len() != before
+    }
+
+    /// Replaces every saved search with `saved`, skipping any that would shadow a
+    /// built-in tool. Returns whether the tool list changed.
+    pub fn replace(&self, saved: Vec<SavedSearch>) -> bool {
+        let (saved, shadowing): (Vec<_>, Vec<_>) = saved
+            .into_iter()
+            .partition(|s| !self.builtin_tools().iter().any(|tool| tool.name == s.name));
+        for s in shadowing {
+            log::warn!("Ignoring saved search '{}': a built-in tool has that name", s.name);
+        }
+        let mut all = self.saved.write().unwrap();
+        if *all == saved {
+            return false;
+        }
+        *all = saved;
+        true
+    }
+}
+
+/// Reads saved searches from a JSON file holding an array of them.
+pub fn load_saved_searches(path: impl AsRef<Path>) -> std::io::Result<Vec<SavedSearch>> {
+    let file = std::fs::File::open(path)?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Tells every operating session that the tool list changed.
+pub fn notify_list_changed(sessions: &SessionStore) {
+    for session in sessions.all().into_iter().filter(|s| s.is_operating()) {
+        session.notify(LIST_CHANGED_NOTIFICATION, json!({}));
+    }
+}
+
+/// The result of a search that failed: an invalid query is reported in the
+/// result with `isError` set, while a cancelled one gets no result at all.
+pub fn search_failed(e: SearchError) -> Result<CallToolResult, Error> {
+    match e {
+        SearchError::Invalid(e) => Ok(CallToolResult::error(e.to_string())),
+        SearchError::Cancelled => Err(cancellation::cancelled_error()),
+    }
+}
+
+/// Runs a tool against the index, searching with `options` and reporting how
+/// far it got to `progress`.
+///
+/// Unknown tool names are a protocol error; everything that goes wrong while
+/// running a known tool is reported in the result with `isError` set.
+pub fn call_tool(
+    wi: &WordIndex,
+    name: &str,
+    arguments: Value,
+    options: &SearchOptions,
+    progress: &mut Progress,
+) -> Result<CallToolResult, Error> {
+    log::debug!("call_tool called with name: '{}', arguments: {}", name, arguments);
+    match name {
+        SEARCH_TOOL => Ok(match serde_json::from_value::<SearchArguments>(arguments) {
+            Ok(args) if !(1..=MAX_SEARCH_LIMIT).contains(&args.limit) => CallToolResult::error(format!(
+                "Invalid arguments for search: limit must be between 1 and {}",
+                MAX_SEARCH_LIMIT
+            )),
+            Ok(args) => {
+                let search = wi.search_ranked(&args.query, &cancellation::current(), options, |done, total| {
+                    progress.report(done, total, format!("Searched {} of {} word occurrences", done, total))
+                });
+                let mut results = match search {
+                    Ok(results) => results,
+                    Err(e) => return search_failed(e),
+                };
+                // The best matches are kept, then put in the requested order
+                let total = results.len();
+                results.truncate(args.limit);
+                ranking::sort(&mut results, args.sort);
+                log::trace!("Results for search tool query '{}': {:?}", args.query, results);
+                let hits: Vec<Hit> = results
+                    .into_iter()
+                    .filter_map(|result| {
+                        wi.fetch(result.line).map(|text| Hit {
+                            id: Hit::id_for(result.line),
+                            line: result.line,
+                            text,
+                            score: Some(result.score),
+                        })
+                    })
+                    .collect();
+                let truncated = total > hits.len();
+                let mut text = if hits.is_empty() {
+                    format!("No lines match '{}'.", args.query)
+                } else {
+                    hits.iter()
+                        .map(|hit| format!("{}: {}", hit.line, hit.text))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                if truncated {
+                    text.push_str(&format!(
+                        "\n(Showing the best {} of {} matching lines; raise limit or narrow the query for more.)",
+                        hits.len(),
+                        total
+                    ));
+                }
+                CallToolResult::structured(text, json!({ "hits": hits, "total": total, "truncated": truncated }))
+            }
+            Err(e) => CallToolResult::error(format!("Invalid arguments for search: {}", e)),
+        }),
+        FETCH_TOOL => Ok(match serde_json::from_value::<FetchArguments>(arguments) {
+            Ok(args) => match wi.fetch(args.line) {
+                Some(text) => {
+                    let hit = Hit {
+                        id: Hit::id_for(args.line),
+                        line: args.line,
+                        text,
+                        score: None,
+                    };
+                    CallToolResult::structured(hit.text.clone(), json!(hit))
+                }
+                None => CallToolResult::error(format!(
+                    "Invalid record ID: line number {} is out of bounds.",
+                    args.line
+                )),
+            },
+            Err(e) => CallToolResult::error(format!("Invalid arguments for fetch: {}", e)),
+        }),
+        _ => {
+            log::warn!("Unknown tool requested: '{}'", name);
+            Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: format!("Unknown tool: {}", name),
+                data: None,
+            })
+        }
+    }
+}
+
+/// Registers the `tools/list` and `tools/call` methods on `handler`, serving
+/// the tools in `registry`.
+pub fn register(handler: &mut Handler, index: SharedIndex, registry: ToolRegistry) {
+    let registry_list = registry.clone();
+    handler.add_method("tools/list", move |params: Params| {
+        let tools = registry_list.tools();
+        async move {
+            log::debug!("RPC 'tools/list' method called with params: {:?}", params);
+            serde_json::to_value(ListToolsResult { tools }).map_err(|e| {
+                log::error!("Failed to serialize ListToolsResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+
+    handler.add_method_with_meta("tools/call", move |params: Params, session: Meta| {
+        let wi = index.load_for(&session);
+        let registry = registry.clone();
+        async move {
+            log::debug!("RPC 'tools/call' method called with params: {:?}", params);
+            let mut progress = Progress::for_request(&session, &params);
+            let call = params.parse::<CallToolParams>().map_err(|e| {
+                log::error!("Failed to parse params for 'tools/call': {:?}", e);
+                Error {
+                    code: ErrorCode::InvalidParams,
+                    message: format!("Invalid parameters for tools/call: {}", e.message),
+                    data: None,
+                }
+            })?;
+            let arguments = call.arguments.unwrap_or_else(|| json!({}));
+            let result = match (registry.saved_search(&call.name), &registry.connector) {
+                (Some(saved), _) => match saved.search_arguments(&arguments) {
+                    Ok(arguments) => call_tool(&wi, SEARCH_TOOL, arguments, &registry.search, &mut progress)?,
+                    Err(e) => CallToolResult::error(e.to_string()),
+                },
+                (None, Some(connector)) => connector
+                    .call_tool(&wi, &call.name, arguments.clone(), &registry.search, &mut progress)
+                    .unwrap_or_else(|| call_tool(&wi, &call.name, arguments, &registry.search, &mut progress))?,
+                (None, None) => call_tool(&wi, &call.name, arguments, &registry.search, &mut progress)?,
+            };
+            serde_json::to_value(result).map_err(|e| {
+                log::error!("Failed to serialize CallToolResult: {}", e);
+                Error::internal_error()
+            })
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::session::Session;
+
+    fn word_index_from_test_db() -> SharedIndex {
+        SharedIndex::new(WordIndex::new("test_db.txt").expect("Failed to load test_db.txt"))
+    }
+
+    fn call(handler: &Handler, params: Value) -> Value {
+        let request = json!({"jsonrpc": "2.0", "method": "tools/call", "params": params, "id": 1});
+        let response = handler
+            .handle_request_sync(&request.to_string(), Arc::new(Session::stateless()))
+            .expect("Handler should produce a response");
+        serde_json::from_str(&response).expect("Response should be valid JSON")
+    }
+
+    #[test]
+    fn test_tools_list_has_schemas() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), ToolRegistry::default());
+        let response = handler
+            .handle_request_sync(
+                r#"{"jsonrpc": "2.0", "method": "tools/list", "id": 1}"#,
+                Arc::new(Session::stateless()),
+            )
+            .unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        let tools = response["result"]["tools"].as_array().expect("tools should be an array");
+        let names: Vec<&str> = tools.iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["search", "fetch"]);
+        for tool in tools {
+            assert_eq!(tool["inputSchema"]["type"], "object");
+            assert_eq!(tool["outputSchema"]["type"], "object");
+        }
+    }
+
+    #[test]
+    fn test_tools_call_search_and_fetch() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), ToolRegistry::default());
+
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "hello"}}));
+        assert_eq!(response["result"]["isError"], false);
+        assert_eq!(response["result"]["content"][0]["type"], "text");
+        assert_eq!(response["result"]["content"][0]["text"], "0: Hello world!");
+        let hit = &response["result"]["structuredContent"]["hits"][0];
+        assert_eq!(hit["id"], "line-0");
+        assert_eq!(hit["line"], 0);
+        assert_eq!(hit["text"], "Hello world!");
+        assert!(hit["score"].as_f64().unwrap() > 0.0);
+
+        let response = call(&handler, json!({"name": "fetch", "arguments": {"line": 1}}));
+        assert_eq!(response["result"]["isError"], false);
+        assert_eq!(response["result"]["content"][0]["text"], "This is a test line.");
+        assert_eq!(
+            response["result"]["structuredContent"],
+            json!({"id": "line-1", "line": 1, "text": "This is a test line."})
+        );
+
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "absent"}}));
+        assert_eq!(
+            response["result"]["structuredContent"],
+            json!({"hits": [], "total": 0, "truncated": false})
+        );
+    }
+
+    #[test]
+    fn test_search_hits_are_ranked() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), ToolRegistry::default());
+        let lines = |response: &Value| -> Vec<u64> {
+            let hits = response["result"]["structuredContent"]["hits"].as_array().unwrap();
+            hits.iter().map(|hit| hit["line"].as_u64().unwrap()).collect()
+        };
+
+        // Line 8 says "line" twice; line 1 is the shortest of the rest
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "line"}}));
+        assert_eq!(lines(&response), vec![8, 1, 2, 6]);
+        let scores: Vec<f64> = response["result"]["structuredContent"]["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["score"].as_f64().unwrap())
+            .collect();
+        assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "line", "sort": "line"}}));
+        assert_eq!(lines(&response), vec![1, 2, 6, 8]);
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "line", "sort": "date"}}));
+        assert_eq!(response["result"]["isError"], true);
+    }
+
+    #[test]
+    fn test_search_hits_are_limited() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), ToolRegistry::default());
+
+        let arguments = json!({"query": "line", "limit": 2, "sort": "line"});
+        let response = call(&handler, json!({"name": "search", "arguments": arguments}));
+        let output = &response["result"]["structuredContent"];
+        let hits = output["hits"].as_array().unwrap();
+        let lines: Vec<u64> = hits.iter().map(|hit| hit["line"].as_u64().unwrap()).collect();
+        assert_eq!(lines, vec![1, 8], "the best two, in line order");
+        assert_eq!(output["total"], 4);
+        assert_eq!(output["truncated"], true);
+        let text = response["result"]["content"][0]["text"].as_str().unwrap();
+        assert!(text.ends_with("(Showing the best 2 of 4 matching lines; raise limit or narrow the query for more.)"));
+
+        // Excluding a missing word matches every line, but not in one response
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "NOT absent"}}));
+        let output = &response["result"]["structuredContent"];
+        assert_eq!(output["hits"].as_array().unwrap().len(), 10);
+        assert_eq!(output["truncated"], false);
+
+        for limit in [0, MAX_SEARCH_LIMIT + 1] {
+            let response = call(&handler, json!({"name": "search", "arguments": {"query": "line", "limit": limit}}));
+            assert_eq!(response["result"]["isError"], true);
+        }
+    }
+
+    #[test]
+    fn test_tools_call_errors() {
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), ToolRegistry::default());
+
+        let response = call(&handler, json!({"name": "fetch", "arguments": {"line": 100}}));
+        assert!(response["error"].is_null());
+        assert_eq!(response["result"]["isError"], true);
+        assert!(response["result"].get("structuredContent").is_none());
+
+        let response = call(&handler, json!({"name": "search", "arguments": {}}));
+        assert_eq!(response["result"]["isError"], true);
+
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "hello OR"}}));
+        assert!(response["error"].is_null());
+        assert_eq!(response["result"]["isError"], true);
+        assert_eq!(
+            response["result"]["content"][0]["text"],
+            "Invalid query: expected a term after OR at position 6"
+        );
+
+        let response = call(&handler, json!({"name": "nope", "arguments": {}}));
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidParams.code());
+    }
+
+    #[test]
+    fn test_connector_contract_replaces_builtins() {
+        let registry = ToolRegistry::default().with_connector(Connector::default());
+        let fetch = registry.tools().into_iter().find(|t| t.name == FETCH_TOOL).unwrap();
+        assert_eq!(fetch.input_schema["required"], json!(["id"]));
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), registry);
+
+        let response = call(&handler, json!({"name": "search", "arguments": {"query": "hello"}}));
+        assert_eq!(response["result"]["structuredContent"]["results"][0]["id"], "line-0");
+        let response = call(&handler, json!({"name": "fetch", "arguments": {"id": "line-0"}}));
+        assert_eq!(response["result"]["structuredContent"]["text"], "Hello world!");
+        let response = call(&handler, json!({"name": "nope", "arguments": {}}));
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidParams.code());
+    }
+
+    #[test]
+    fn test_saved_searches_are_listed_and_callable() {
+        let registry = ToolRegistry::default();
+        let mut handler = lifecycle::new_handler();
+        register(&mut handler, word_index_from_test_db(), registry.clone());
+        let empty_lines = SavedSearch {
+            name: "empty-lines".into(),
+            description: "Lines mentioning empty lines.".into(),
+            query: "empty line".into(),
+            annotations: None,
+        };
+        assert!(registry.add(empty_lines.clone()).unwrap());
+        assert!(!registry.add(empty_lines).unwrap(), "adding the same tool again changes nothing");
+
+        let names: Vec<String> = registry.tools().into_iter().map(|t| t.name).collect();
+        assert_eq!(names, vec!["search", "fetch", "empty-lines"]);
+        let response = call(&handler, json!({"name": "empty-lines"}));
+        assert_eq!(response["result"]["content"][0]["text"], "8: A line after an empty line.\n6: An empty line follows this one.");
+        let response = call(&handler, json!({"name": "empty-lines", "arguments": {"query": "after"}}));
+        assert_eq!(response["result"]["content"][0]["text"], "8: A line after an empty line.");
+
+        let either = SavedSearch {
+            name: "hello-or-numbers".into(),
+            description: "Greetings and numbers.".into(),
+            query: "hello OR numbers".into(),
+            annotations: None,
+        };
+        assert!(registry.add(either).unwrap());
+        let lines = |response: &Value| -> Vec<u64> {
+            let hits = response["result"]["structuredContent"]["hits"].as_array().unwrap();
+            let mut lines: Vec<u64> = hits.iter().map(|hit| hit["line"].as_u64().unwrap()).collect();
+            lines.sort_unstable();
+            lines
+        };
+        let response = call(&handler, json!({"name": "hello-or-numbers"}));
+        assert_eq!(lines(&response), vec![0, 5]);
+        // The extra words narrow the whole saved query, not just its last alternative
+        let response = call(&handler, json!({"name": "hello-or-numbers", "arguments": {"query": "123"}}));
+        assert_eq!(lines(&response), vec![5]);
+        let response = call(&handler, json!({"name": "hello-or-numbers", "arguments": {"query": "x) OR (world"}}));
+        assert_eq!(response["result"]["isError"], true);
+
+        assert!(registry.remove("empty-lines"));
+        let response = call(&handler, json!({"name": "empty-lines"}));
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidParams.code());
+    }
+
+    #[test]
+    fn test_tool_annotations_default_and_overrides() {
+        let registry = ToolRegistry::default();
+        let search = &registry.tools()[0];
+        assert_eq!(
+            serde_json::to_value(&search.annotations).unwrap(),
+            json!({"title": "Search records", "readOnlyHint": true, "idempotentHint": true, "openWorldHint": false})
+        );
+
+        let overrides: HashMap<String, ToolAnnotations> =
+            serde_json::from_value(json!({"fetch": {"title": "Read a line", "openWorldHint": true}})).unwrap();
+        let registry = ToolRegistry::with_annotations(overrides);
+        registry
+            .add(SavedSearch {
+                name: "greetings".into(),
+                description: "Greetings.".into(),
+                query: "hello".into(),
+                annotations: Some(ToolAnnotations {
+                    title: Some("Find greetings".into()),
+                    ..Default::default()
+                }),
+            })
+            .unwrap();
+        let tools = registry.tools();
+        assert_eq!(tools[1].annotations.title.as_deref(), Some("Read a line"));
+        assert_eq!(tools[1].annotations.open_world_hint, Some(true));
+        assert_eq!(tools[1].annotations.read_only_hint, Some(true), "unset hints keep their defaults");
+        assert_eq!(tools[2].annotations.title.as_deref(), Some("Find greetings"));
+        assert_eq!(tools[2].annotations.read_only_hint, Some(true));
+    }
+
+    #[test]
+    fn test_saved_searches_cannot_shadow_builtins() {
+        let registry = ToolRegistry::default();
+        let shadow = SavedSearch {
+            name: SEARCH_TOOL.into(),
+            description: "Not the real search.".into(),
+            query: "hello".into(),
+            annotations: None,
+        };
+        assert!(registry.add(shadow.clone()).is_err());
+        assert!(!registry.replace(vec![shadow]));
+        assert_eq!(registry.tools().len(), 2);
+    }
+}
@@ -0,0 +1,248 @@
+//! Keeps the loaded [`WordIndex`] in step with the database file on disk.
+//!
+//! Handlers read the index through a [`SharedIndex`], which the watcher swaps out
+//! whenever the file changes. Each swap is reported as an [`IndexChange`] so that
+//! subscribed clients can be told which resources moved.
+//!
+//! The `--tools` file of saved searches is watched the same way by [`ToolsWatcher`].
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::resources;
+use crate::session::{Session, SessionStore};
+use crate::tools::{self, ToolRegistry};
+use crate::WordIndex;
+
+/// The index currently being served, shared by every handler.
+#[derive(Clone, Debug)]
+pub struct SharedIndex {
+    current: Arc<RwLock<Arc<WordIndex>>>,
+}
+
+impl SharedIndex {
+    pub fn new(word_index: WordIndex) -> Self {
+        SharedIndex {
+            current: Arc::new(RwLock::new(Arc::new(word_index))),
+        }
+    }
+
+    /// Returns the index as of now; later swaps do not affect the returned value.
+    pub fn load(&self) -> Arc<WordIndex> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// The index `session` should see: its own, built from its roots, or else
+    /// the shared one.
+    pub fn load_for(&self, session: &Session) -> Arc<WordIndex> {
+        session.index().unwrap_or_else(|| self.load())
+    }
+
+    /// Installs `word_index` and reports how it differs from the one it replaces.
+    pub fn replace(&self, word_index: WordIndex) -> IndexChange {
+        let new = Arc::new(word_index);
+        let old = std::mem::replace(&mut *self.current.write().unwrap(), Arc::clone(&new));
+        IndexChange::between(&old, &new)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IndexChange {
+    /// Lines whose text changed, appeared or disappeared.
+    pub changed_lines: Vec<usize>,
+    /// Whether the set of lines, and so the resource list, changed.
+    pub list_changed: bool,
+}
+
+impl IndexChange {
+    pub fn between(old: &WordIndex, new: &WordIndex) -> Self {
+        let longest = old.lines.len().max(new.lines.len());
+        let changed_lines = (0..longest)
+            .filter(|&n| old.lines.get(n) != new.lines.get(n))
+            .collect();
+        IndexChange {
+            changed_lines,
+            list_changed: old.lines.len() != new.lines.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed_lines.is_empty() && !self.list_changed
+    }
+}
+
+/// Polls a database file and reloads it into a [`SharedIndex`] when it changes.
+#[derive(Debug)]
+pub struct DbWatcher {
+    path: PathBuf,
+    index: SharedIndex,
+    last_seen: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl DbWatcher {
+    pub fn new(path: impl AsRef<Path>, index: SharedIndex) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let last_seen = Mutex::new(file_stamp(&path));
+        DbWatcher { path, index, last_seen }
+    }
+
+    pub fn index(&self) -> &SharedIndex {
+        &self.index
+    }
+
+    /// Reloads the file if its modification time or size changed since the last
+    /// look. Returns the change, or `None` if the file was left alone.
+    pub fn poll(&self) -> std::io::Result<Option<IndexChange>> {
+        if *self.last_seen.lock().unwrap() == file_stamp(&self.path) {
+            return Ok(None);
+        }
+        log::info!("{} changed on disk, reloading.", self.path.display());
+        self.reload(|_, _| {}).map(Some)
+    }
+
+    /// Reloads the file unconditionally, calling `on_progress(lines_indexed,
+    /// total_lines)` as it goes.
+    pub fn reload(&self, on_progress: impl FnMut(usize, usize)) -> std::io::Result<IndexChange> {
+        *self.last_seen.lock().unwrap() = file_stamp(&self.path);
+        let word_index = WordIndex::new_with_progress(&self.path.to_string_lossy(), on_progress)?;
+        let change = self.index.replace(word_index);
+        log::debug!("Reload of {} changed: {:?}", self.path.display(), change);
+        Ok(change)
+    }
+
+    /// Polls every `interval` forever, telling sessions in `sessions` about changes.
+    /// Polling, which may read and index the file, runs on the blocking pool.
+    pub async fn run(self: Arc<Self>, interval: Duration, sessions: SessionStore) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let watcher = Arc::clone(&self);
+            match tokio::task::spawn_blocking(move || watcher.poll()).await {
+                Ok(Ok(Some(change))) if !change.is_empty() => resources::notify_changes(&sessions, &change),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => log::error!("Failed to reload {}: {}", self.path.display(), e),
+                Err(e) => log::error!("Reloading {} failed: {}", self.path.display(), e),
+            }
+        }
+    }
+}
+
+/// Polls the saved searches file and reloads it into a [`ToolRegistry`].
+#[derive(Debug)]
+pub struct ToolsWatcher {
+    path: PathBuf,
+    registry: ToolRegistry,
+    last_seen: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl ToolsWatcher {
+    pub fn new(path: impl AsRef<Path>, registry: ToolRegistry) -> Self {
+        ToolsWatcher {
+            path: path.as_ref().to_path_buf(),
+            registry,
+            last_seen: Mutex::new(None),
+        }
+    }
+
+    /// Reloads the file if it changed since the last look. Returns whether the
+    /// tool list changed.
+    pub fn poll(&self) -> std::io::Result<bool> {
+        let stamp = file_stamp(&self.path);
+        {
+            let mut last_seen = self.last_seen.lock().unwrap();
+            if *last_seen == stamp {
+                return Ok(false);
+            }
+            *last_seen = stamp;
+        }
+        log::info!("Loading saved searches from {}.", self.path.display());
+        let saved = tools::load_saved_searches(&self.path)?;
+        Ok(self.registry.replace(saved))
+    }
+
+    /// Polls every `interval` forever, telling sessions in `sessions` about changes.
+    /// Polling, which may read and parse the file, runs on the blocking pool.
+    pub async fn run(self: Arc<Self>, interval: Duration, sessions: SessionStore) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let watcher = Arc::clone(&self);
+            match tokio::task::spawn_blocking(move || watcher.poll()).await {
+                Ok(Ok(true)) => tools::notify_list_changed(&sessions),
+                Ok(Ok(false)) => {}
+                Ok(Err(e)) => log::error!("Failed to load saved searches from {}: {}", self.path.display(), e),
+                Err(e) => log::error!("Loading saved searches from {} failed: {}", self.path.display(), e),
+            }
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn index_of(contents: &str) -> WordIndex {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", contents).unwrap();
+        WordIndex::new(file.path().to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_index_change_between() {
+        let old = index_of("a\nb\nc\n");
+        assert_eq!(IndexChange::between(&old, &index_of("a\nb\nc\n")), IndexChange::default());
+        assert_eq!(
+            IndexChange::between(&old, &index_of("a\nB\nc\n")),
+            IndexChange {
+                changed_lines: vec![1],
+                list_changed: false
+            }
+        );
+        assert_eq!(
+            IndexChange::between(&old, &index_of("a\nb\n")),
+            IndexChange {
+                changed_lines: vec![2],
+                list_changed: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_watcher_reloads_changed_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "first line").unwrap();
+        let index = SharedIndex::new(WordIndex::new(file.path().to_str().unwrap()).unwrap());
+        let watcher = DbWatcher::new(file.path(), index.clone());
+        assert_eq!(watcher.poll().unwrap(), None);
+
+        writeln!(file, "second line").unwrap();
+        let change = watcher.poll().unwrap().expect("file change should be noticed");
+        assert_eq!(change.changed_lines, vec![1]);
+        assert!(change.list_changed);
+        assert_eq!(index.load().search("second"), vec![1]);
+        assert_eq!(watcher.poll().unwrap(), None);
+    }
+
+    #[test]
+    fn test_tools_watcher_reloads_saved_searches() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, r#"[{{"name": "greetings", "description": "Greetings.", "query": "hello"}}]"#).unwrap();
+        let registry = ToolRegistry::default();
+        let watcher = ToolsWatcher::new(file.path(), registry.clone());
+        assert!(watcher.poll().unwrap());
+        assert!(registry.saved_search("greetings").is_some());
+        assert!(!watcher.poll().unwrap());
+
+        std::fs::write(file.path(), "[]").unwrap();
+        assert!(watcher.poll().unwrap());
+        assert!(registry.saved_search("greetings").is_none());
+    }
+}